@@ -0,0 +1,136 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const METRICS_PATH: &str = "/metrics";
+
+// How long a client has to send its request and read the response, however
+// slowly it does so
+const HTTP_DEADLINE: Duration = Duration::from_secs(5);
+const MAX_HTTP_REQUEST_LENGTH: u64 = 8192;
+// Connections past this are closed right away, so that clients that never
+// finish their requests can't use up threads
+const MAX_CONNECTIONS: usize = 16;
+
+// Answers requests on the listener until it fails, from a thread per connection
+// so that a slow client does not hold up scrapes
+pub fn serve<R, E>(listener: TcpListener, render: R, on_error: E)
+where
+    R: Fn() -> String + Send + Sync + 'static,
+    E: Fn(io::Error) + Send + Sync + 'static
+{
+    let render = Arc::new(render);
+    let on_error = Arc::new(on_error);
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                on_error(e);
+                continue;
+            }
+        };
+
+        if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            continue;
+        }
+
+        let (render, thread_on_error, thread_connections) = (Arc::clone(&render), Arc::clone(&on_error), Arc::clone(&connections));
+        let res = thread::Builder::new().spawn(move || {
+            if let Err(e) = handle_connection(stream, || render()) {
+                thread_on_error(e);
+            }
+
+            thread_connections.fetch_sub(1, Ordering::SeqCst);
+        });
+
+        if let Err(e) = res {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            on_error(e);
+        }
+    }
+}
+
+// Answers a single HTTP request, with what render returns for METRICS_PATH.
+// Metrics are only rendered for requests that ask for them.
+pub fn handle_connection<F>(stream: TcpStream, render: F) -> io::Result<()>
+where
+    F: FnOnce() -> String
+{
+    let mut stream = Deadline {
+        stream,
+        deadline: Instant::now() + HTTP_DEADLINE
+    };
+
+    let mut reader = BufReader::new((&mut stream).take(MAX_HTTP_REQUEST_LENGTH));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Headers are not needed, but are read so that the client doesn't get a reset
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+
+    let (status, body) = match (method, path) {
+        ("GET", METRICS_PATH) => ("200 OK", render()),
+        ("GET", _) => ("404 Not Found", String::from("Not found\n")),
+        _ => ("405 Method Not Allowed", String::from("Method not allowed\n"))
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes())
+}
+
+// Label values may contain anything, such as user names sent by servers
+pub fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// A stream whose reads and writes fail once the deadline has passed, instead of
+// each of them getting a timeout of its own
+struct Deadline {
+    stream: TcpStream,
+    deadline: Instant
+}
+
+impl Deadline {
+    fn remaining(&self) -> io::Result<Duration> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+
+        if remaining.is_zero() {
+            Err(io::ErrorKind::TimedOut.into())
+        } else {
+            Ok(remaining)
+        }
+    }
+}
+
+impl Read for Deadline {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.set_read_timeout(Some(self.remaining()?))?;
+        self.stream.read(buf)
+    }
+}
+
+impl Write for Deadline {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.set_write_timeout(Some(self.remaining()?))?;
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
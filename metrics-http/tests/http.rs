@@ -0,0 +1,66 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use metrics_http::{escape_label, handle_connection, serve, METRICS_PATH};
+
+// Sends a request to a server answering a single connection, and returns its response
+fn request(request: &str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        handle_connection(stream, || "whered_sessions 3\n".to_string()).unwrap();
+    });
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    server.join().unwrap();
+
+    response
+}
+
+#[test]
+fn metrics() {
+    let response = request(&format!("GET {METRICS_PATH} HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n\r\n"));
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+    assert!(response.contains("Content-Length: 18\r\n"));
+    assert!(response.ends_with("\r\n\r\nwhered_sessions 3\n"));
+}
+
+#[test]
+fn other_paths() {
+    assert!(request("GET / HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    assert!(request(&format!("POST {METRICS_PATH} HTTP/1.1\r\n\r\n")).starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+}
+
+#[test]
+fn slow_clients_do_not_hold_up_others() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || serve(listener, || "whered_sessions 3\n".to_string(), |_| {}));
+
+    // A client that never finishes its request
+    let mut slow = TcpStream::connect(address).unwrap();
+    slow.write_all(b"GET /met").unwrap();
+
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    stream.write_all(format!("GET {METRICS_PATH} HTTP/1.1\r\n\r\n").as_bytes()).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+}
+
+#[test]
+fn label_values() {
+    assert_eq!(escape_label("alice"), "alice");
+    assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+}
@@ -1,4 +1,7 @@
+use std::net::SocketAddr;
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use clap::Parser;
+use crate::config::HostDisplay;
 
 #[derive(Parser, Debug)]
 #[command(name = "where", version, about)]
@@ -6,4 +9,106 @@ pub struct Args {
     /// Generate a config file when none is available
     #[arg(short = 'c', long)]
     pub generate_config: bool,
+
+    /// Show a summary of each server instead of the list of sessions
+    #[arg(short = 'H', long)]
+    pub hosts: bool,
+
+    /// Which name to show for each server, overriding global.host_display
+    #[arg(long, value_enum)]
+    pub host_display: Option<HostDisplay>,
+
+    /// Show past logins instead of the current sessions
+    #[arg(long, conflicts_with = "hosts")]
+    pub history: bool,
+
+    /// Only show sessions still open after this time, either relative (e.g. 30m, 12h, 7d, 2w) or absolute (YYYY-MM-DD [HH:MM[:SS]])
+    #[arg(long, requires = "history", value_parser = parse_time, default_value = "7d")]
+    pub since: i64,
+
+    /// Only show logins before this time, in the same format as --since
+    #[arg(long, requires = "history", value_parser = parse_time)]
+    pub until: Option<i64>,
+
+    /// Only show logins from this user
+    #[arg(short = 'u', long, requires = "history")]
+    pub user: Option<String>,
+
+    /// Serve Prometheus metrics about all servers on this address (e.g. :9615) instead of printing sessions
+    #[arg(long, conflicts_with_all = ["hosts", "history"], value_parser = parse_listen_addr)]
+    pub exporter: Option<SocketAddr>,
+}
+
+fn parse_listen_addr(value: &str) -> Result<SocketAddr, String> {
+    // Listen on all interfaces when only a port is given
+    let value = if value.starts_with(':') {
+        format!("0.0.0.0{value}")
+    } else {
+        value.to_string()
+    };
+
+    value.parse().map_err(|_| format!("invalid address: {value}"))
+}
+
+fn parse_time(value: &str) -> Result<i64, String> {
+    let now = Utc::now().timestamp();
+
+    if let Some(unit) = value.chars().last().filter(|c| c.is_ascii_alphabetic()) {
+        let amount: i64 = value[..value.len() - 1].parse()
+            .map_err(|_| format!("invalid relative time: {value}"))?;
+
+        let seconds = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return Err(format!("unknown time unit '{unit}', expected one of s, m, h, d, w"))
+        };
+
+        return amount.checked_mul(seconds)
+            .and_then(|seconds| now.checked_sub(seconds))
+            .ok_or_else(|| format!("relative time out of range: {value}"));
+    }
+
+    let datetime = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok().and_then(|d| d.and_hms_opt(0, 0, 0)))
+        .ok_or_else(|| format!("invalid time: {value}"))?;
+
+    Local.from_local_datetime(&datetime)
+        .earliest()
+        .map(|d| d.timestamp())
+        .ok_or_else(|| format!("time does not exist in the local time zone: {value}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_times() {
+        let now = Utc::now().timestamp();
+        let since = parse_time("2h").unwrap();
+
+        assert!((now - 7200..=now - 7200 + 5).contains(&since));
+        assert!(parse_time("3y").is_err());
+        assert!(parse_time("xd").is_err());
+    }
+
+    #[test]
+    fn relative_times_out_of_range() {
+        assert!(parse_time(&format!("{}w", i64::MAX)).is_err());
+        assert!(parse_time(&format!("{}s", i64::MIN)).is_err());
+    }
+
+    #[test]
+    fn absolute_times() {
+        let expected = Local.with_ymd_and_hms(2024, 3, 1, 12, 30, 0).earliest().unwrap().timestamp();
+
+        assert_eq!(parse_time("2024-03-01 12:30").unwrap(), expected);
+        assert_eq!(parse_time("2024-03-01 12:30:00").unwrap(), expected);
+        assert!(parse_time("2024-13-01").is_err());
+    }
 }
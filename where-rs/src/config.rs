@@ -1,10 +1,13 @@
+use std::collections::HashSet;
 use std::{env, fs};
 use std::path::PathBuf;
+use clap::ValueEnum;
 use serde::Deserialize;
 use crate::args::Args;
 
 const TIMEOUT: u64 = 2000;
 const MAX_SEND_RETRIES: usize = 3;
+const EXPORTER_INTERVAL: u64 = 15000;
 const CONFIG_FILENAME: &str = "where.toml";
 
 #[derive(Deserialize, Debug, Default)]
@@ -21,7 +24,23 @@ pub struct GlobalConfig {
     pub max_retries: usize,
     pub include_inactive: bool,
     pub port: u16,
-    pub source: String
+    pub source: String,
+    pub host_display: HostDisplay,
+    pub hostname_warnings: bool,
+    pub exporter_interval: u64,
+    pub exporter_per_user: bool
+}
+
+#[derive(Deserialize, ValueEnum, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HostDisplay {
+    /// The label configured for the server
+    #[default]
+    Label,
+    /// The host name reported by the server
+    Server,
+    /// Both the label and the host name reported by the server
+    Both
 }
 
 #[derive(Deserialize, Debug)]
@@ -40,7 +59,11 @@ impl Default for GlobalConfig {
             max_retries: MAX_SEND_RETRIES,
             include_inactive: true,
             port: 15,
-            source: "Local".to_string()
+            source: "Local".to_string(),
+            host_display: HostDisplay::Label,
+            hostname_warnings: true,
+            exporter_interval: EXPORTER_INTERVAL,
+            exporter_per_user: true
         }
     }
 }
@@ -73,7 +96,22 @@ impl Config {
         ]
     }
 
-    pub fn build(args: Args) -> Self {
+    // Servers are told apart by their label, such as in the metrics of the exporter
+    fn validate(&self) -> Result<(), String> {
+        let mut labels = HashSet::new();
+
+        for server in &self.server {
+            let label = server.get_label();
+
+            if !labels.insert(label.clone()) {
+                return Err(format!("Several servers are labelled {label:?}, give them different labels"));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn build(args: &Args) -> Self {
         let config: Option<Config> = Self::get_config_locations()
             .iter()
             .flat_map(|path| fs::read_to_string(path).ok())
@@ -83,6 +121,11 @@ impl Config {
             }))
             .next();
 
+        if let Some(Err(e)) = config.as_ref().map(Config::validate) {
+            eprintln!("where: Invalid configuration file: {e}");
+            std::process::exit(1);
+        }
+
         if args.generate_config {
             let default_config = include_str!("../default_config.toml");
 
@@ -127,3 +170,43 @@ impl Config {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicate_labels() {
+        let config: Config = toml::from_str(r#"
+            [[server]]
+            endpoint = "10.0.0.1"
+            label = "web"
+
+            [[server]]
+            endpoint = "10.0.0.2"
+            label = "web"
+        "#).unwrap();
+        assert!(config.validate().is_err());
+
+        // Servers without a label are known by their endpoint
+        let config: Config = toml::from_str(r#"
+            [[server]]
+            endpoint = "10.0.0.1"
+
+            [[server]]
+            endpoint = "10.0.0.2"
+            label = "10.0.0.1"
+        "#).unwrap();
+        assert!(config.validate().is_err());
+
+        let config: Config = toml::from_str(r#"
+            [[server]]
+            endpoint = "10.0.0.1"
+
+            [[server]]
+            endpoint = "10.0.0.2"
+            label = "web"
+        "#).unwrap();
+        assert!(config.validate().is_ok());
+    }
+}
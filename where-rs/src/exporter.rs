@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use metrics_http::{escape_label, METRICS_PATH};
+use whrd::client::Client;
+use whrd::error::{Report, WhereResult};
+use crate::config::Config;
+use crate::servers;
+
+// The last known state of each server, by label
+type Fleet = BTreeMap<String, HostState>;
+
+#[derive(Default)]
+struct HostState {
+    up: bool,
+    rtt: Option<Duration>,
+    retries: u64,
+    failures: u64,
+    active: u64,
+    inactive: u64,
+    users: BTreeMap<String, u64>
+}
+
+pub fn start_exporter(listen_addr: SocketAddr, config: Config) -> WhereResult<()> {
+    let listener = TcpListener::bind(listen_addr)?;
+    println!("where: Serving metrics on http://{listen_addr}{METRICS_PATH}");
+
+    let fleet = Arc::new(Mutex::new(Fleet::new()));
+    let client = servers::build_client(&config.global, &config.server);
+
+    {
+        let fleet = Arc::clone(&fleet);
+        let interval = Duration::from_millis(config.global.exporter_interval);
+        let per_user = config.global.exporter_per_user;
+
+        thread::spawn(move || loop {
+            poll(&client, &fleet, per_user);
+            thread::sleep(interval);
+        });
+    }
+
+    metrics_http::serve(
+        listener,
+        move || render(&fleet.lock().unwrap_or_else(|e| e.into_inner())),
+        |e| eprintln!("where: Unable to answer metrics request: {e}")
+    );
+
+    Ok(())
+}
+
+fn poll(client: &Client, fleet: &Mutex<Fleet>, per_user: bool) {
+    let responses = client.sessions();
+    let mut fleet = fleet.lock().unwrap_or_else(|e| e.into_inner());
+
+    for response in responses {
+        let host = fleet.entry(response.label).or_default();
+        host.retries += response.attempts.saturating_sub(1) as u64;
+
+        match response.result {
+            Ok(collection) => {
+                host.up = true;
+                host.rtt = response.rtt;
+                host.active = 0;
+                host.inactive = 0;
+                host.users.clear();
+
+                for session in collection.into_vec() {
+                    if session.active {
+                        host.active += 1;
+                    } else {
+                        host.inactive += 1;
+                    }
+
+                    if per_user {
+                        *host.users.entry(session.user).or_default() += 1;
+                    }
+                }
+            }
+            Err(e) => {
+                // Only report servers going down, instead of on every poll
+                if host.up || host.failures == 0 {
+                    eprintln!("where: {}", Report(&e));
+                }
+
+                host.up = false;
+                host.failures += 1;
+            }
+        }
+    }
+}
+
+fn render(fleet: &Fleet) -> String {
+    let mut out = String::new();
+
+    out.push_str("# HELP where_server_up Whether the server answered the last poll.\n");
+    out.push_str("# TYPE where_server_up gauge\n");
+    for (host, state) in fleet {
+        let _ = writeln!(out, "where_server_up{{host=\"{}\"}} {}", escape_label(host), state.up as u8);
+    }
+
+    out.push_str("# HELP where_server_rtt_seconds Round-trip time of the last successful poll.\n");
+    out.push_str("# TYPE where_server_rtt_seconds gauge\n");
+    for (host, state) in fleet {
+        if let Some(rtt) = state.rtt {
+            let _ = writeln!(out, "where_server_rtt_seconds{{host=\"{}\"}} {}", escape_label(host), rtt.as_secs_f64());
+        }
+    }
+
+    out.push_str("# HELP where_server_retries_total Requests sent again after a server did not answer in time.\n");
+    out.push_str("# TYPE where_server_retries_total counter\n");
+    for (host, state) in fleet {
+        let _ = writeln!(out, "where_server_retries_total{{host=\"{}\"}} {}", escape_label(host), state.retries);
+    }
+
+    out.push_str("# HELP where_server_failures_total Polls that the server did not answer.\n");
+    out.push_str("# TYPE where_server_failures_total counter\n");
+    for (host, state) in fleet {
+        let _ = writeln!(out, "where_server_failures_total{{host=\"{}\"}} {}", escape_label(host), state.failures);
+    }
+
+    out.push_str("# HELP where_sessions Sessions reported by the server, by state.\n");
+    out.push_str("# TYPE where_sessions gauge\n");
+    for (host, state) in fleet.iter().filter(|(_, state)| state.up) {
+        let _ = writeln!(out, "where_sessions{{host=\"{}\",state=\"active\"}} {}", escape_label(host), state.active);
+        let _ = writeln!(out, "where_sessions{{host=\"{}\",state=\"inactive\"}} {}", escape_label(host), state.inactive);
+    }
+
+    if fleet.values().any(|state| state.up && !state.users.is_empty()) {
+        out.push_str("# HELP where_user_sessions Sessions reported by the server, by user.\n");
+        out.push_str("# TYPE where_user_sessions gauge\n");
+        for (host, state) in fleet.iter().filter(|(_, state)| state.up) {
+            for (user, count) in &state.users {
+                let _ = writeln!(out, "where_user_sessions{{host=\"{}\",user=\"{}\"}} {count}", escape_label(host), escape_label(user));
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fleet(users: &[(&str, u64)]) -> Fleet {
+        let host = HostState {
+            up: true,
+            active: 2,
+            users: users.iter().map(|(user, count)| (user.to_string(), *count)).collect(),
+            ..Default::default()
+        };
+
+        Fleet::from([("server".to_string(), host)])
+    }
+
+    #[test]
+    fn user_sessions() {
+        let out = render(&fleet(&[("alice", 1), ("b\"ob", 1)]));
+
+        assert!(out.contains("where_sessions{host=\"server\",state=\"active\"} 2\n"));
+        assert!(out.contains("where_user_sessions{host=\"server\",user=\"alice\"} 1\n"));
+        assert!(out.contains("where_user_sessions{host=\"server\",user=\"b\\\"ob\"} 1\n"));
+    }
+
+    #[test]
+    fn without_user_sessions() {
+        let out = render(&fleet(&[]));
+
+        assert!(out.contains("where_sessions{host=\"server\",state=\"active\"} 2\n"));
+        assert!(!out.contains("where_user_sessions"));
+    }
+}
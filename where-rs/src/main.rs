@@ -1,47 +1,134 @@
 mod config;
+mod exporter;
 mod servers;
 mod ui;
 mod args;
 
 use clap::Parser;
 use args::Args;
-use whrd::error::WhereResult;
+use whrd::error::{ErrorCode, Report, WhereError, WhereResult};
+use whrd::HistoryQuery;
 use config::{Config, Server};
+use ui::HostSummary;
 
 fn main() {
     if let Err(e) = start_client() {
-        eprintln!("where: {}", e);
-        std::process::exit(1);
+        eprintln!("where: {}", Report(&e));
+        std::process::exit(exit_status(&e));
+    }
+}
+
+// Exit statuses follow sysexits.h, so that scripts can tell failures apart
+fn exit_status(error: &WhereError) -> i32 {
+    match error.code() {
+        ErrorCode::Address => 68,
+        ErrorCode::Io => 74,
+        ErrorCode::Timeout => 75,
+        ErrorCode::Protocol | ErrorCode::ProtocolVersion => 76,
+        ErrorCode::AccessDenied => 77
     }
 }
 
 fn start_client() -> WhereResult<()> {
     let args = Args::parse();
-    let config = Config::build(args);
+    let config = Config::build(&args);
+
+    if args.history {
+        return start_history(&args, config);
+    }
+
+    if let Some(listen_addr) = args.exporter {
+        return exporter::start_exporter(listen_addr, config);
+    }
+
     let global_config = config.global;
+    let host_display = args.host_display.unwrap_or(global_config.host_display);
 
     let servers: Vec<Server> = config.server;
+    let client = servers::build_client(&global_config, &servers);
     let mut sessions = vec![];
+    let mut hosts = vec![];
+    let mut exit_error = None;
 
-    for server in servers {
-        let res = match server.process(&global_config) {
+    for (server, response) in servers.iter().zip(client.sessions()) {
+        let res = match response.result {
             Ok(collection) => {
                 collection
             }
             Err(e) => {
-                eprintln!("where: {e}");
+                eprintln!("where: {}", Report(&e));
 
                 if !server.failsafe.unwrap_or(false) {
-                    std::process::exit(1);
+                    // Showing which servers are down is the point of --hosts,
+                    // so the others are still shown before failing
+                    if !args.hosts {
+                        std::process::exit(exit_status(&e));
+                    }
+
+                    exit_error.get_or_insert(e);
                 }
 
+                hosts.push(HostSummary::unreachable(response.label));
                 continue
             }
         };
 
-        sessions.extend(res.into_vec());
+        let (mut inner, host_info) = res.into_parts();
+
+        if global_config.hostname_warnings {
+            server.check_hostname(host_info.as_ref());
+        }
+
+        let name = server.get_display_name(host_display, host_info.as_ref());
+        for session in &mut inner {
+            // Sessions relayed from another host keep its name
+            if session.host.as_deref() == Some(response.label.as_str()) {
+                session.host = Some(name.clone());
+            }
+        }
+
+        hosts.push(HostSummary::new(name, host_info, &inner));
+        sessions.extend(inner);
+    }
+
+    if args.hosts {
+        ui::print_hosts(hosts);
+
+        if let Some(e) = exit_error {
+            std::process::exit(exit_status(&e));
+        }
+    } else {
+        ui::print_summary(sessions, global_config);
+    }
+
+    Ok(())
+}
+
+fn start_history(args: &Args, config: Config) -> WhereResult<()> {
+    let global_config = config.global;
+    let mut entries = vec![];
+
+    let query = HistoryQuery {
+        since: args.since,
+        until: args.until.unwrap_or(i64::MAX),
+        user: args.user.clone()
+    };
+
+    let client = servers::build_client(&global_config, &config.server);
+
+    for (server, response) in config.server.iter().zip(client.history(query)) {
+        match response.result {
+            Ok(history) => entries.extend(history.into_vec()),
+            Err(e) => {
+                eprintln!("where: {}", Report(&e));
+
+                if !server.failsafe.unwrap_or(false) {
+                    std::process::exit(exit_status(&e));
+                }
+            }
+        }
     }
 
-    ui::print_summary(sessions, global_config);
+    ui::print_history(entries, global_config);
     Ok(())
 }
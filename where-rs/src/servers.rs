@@ -1,68 +1,61 @@
-use std::io::ErrorKind;
-use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
 use std::time::Duration;
-use whrd::error::{WhereError, WhereResult};
-use whrd::{MAX_PAYLOAD_LENGTH, SessionCollection, WHERED_MAGIC};
-use crate::config::{GlobalConfig, Server};
+use whrd::client::{self, Client};
+use whrd::HostInfo;
+use crate::config::{GlobalConfig, HostDisplay, Server};
 
 impl Server {
-    fn get_address(&self, config: &GlobalConfig) -> WhereResult<SocketAddr> {
-        let res: SocketAddr = match self.endpoint.to_socket_addrs() {
-            Ok(mut addr) => {
-                addr.find(|i| i.is_ipv4()).unwrap()
-            },
-            Err(_) => {
-                let mut endpoint = self.endpoint.clone();
-                let port = config.port.to_string();
+    pub fn get_label(&self) -> String {
+        self.label.clone().unwrap_or(self.endpoint.to_owned())
+    }
 
-                endpoint.push(':');
-                endpoint.push_str(&port);
-                endpoint.to_socket_addrs()?.find(|i| i.is_ipv4()).unwrap()
-            }
-        };
+    pub fn get_display_name(&self, mode: HostDisplay, host_info: Option<&HostInfo>) -> String {
+        let label = self.get_label();
 
-        Ok(res)
+        // Fall back to the label for servers that do not report their host name
+        match (mode, host_info) {
+            (HostDisplay::Label, _) | (_, None) => label,
+            (HostDisplay::Server, Some(info)) => info.hostname.clone(),
+            (HostDisplay::Both, Some(info)) if info.hostname == label => label,
+            (HostDisplay::Both, Some(info)) => format!("{label} ({})", info.hostname)
+        }
     }
 
-    fn create_socket(&self, address: &SocketAddr, timeout: Duration) -> WhereResult<UdpSocket> {
-        let socket = UdpSocket::bind(if address.is_ipv4() {
-            "0.0.0.0:0"
-        } else {
-            "[::]:0"
-        })?;
-        socket.set_read_timeout(Some(timeout))?;
+    pub fn check_hostname(&self, host_info: Option<&HostInfo>) {
+        let (Some(label), Some(info)) = (&self.label, host_info) else {
+            return;
+        };
+
+        let short_name = info.hostname.split('.').next().unwrap_or_default();
 
-        Ok(socket)
+        if !label.eq_ignore_ascii_case(&info.hostname) && !label.eq_ignore_ascii_case(short_name) {
+            eprintln!("where: Server {label} reports its host name as {}", info.hostname);
+        }
     }
 
-    fn attempt_fetch(socket: &UdpSocket, address: &SocketAddr, mut buf: [u8; MAX_PAYLOAD_LENGTH], label: &str) -> WhereResult<Option<SessionCollection>> {
-        socket.send_to(&WHERED_MAGIC, address)?;
+    fn to_client_server(&self) -> client::Server {
+        let mut server = client::Server::new(&self.endpoint);
 
-        match socket.recv_from(&mut buf) {
-            Ok(_) => {
-                let collection = SessionCollection::from_udp_payload(buf, label)?;
-                Ok(Some(collection))
-            },
-            Err(e) if e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::WouldBlock => Ok(None),
-            Err(e) => Err(WhereError::from(e)),
+        if let Some(label) = &self.label {
+            server = server.with_label(label);
         }
-    }
 
-    pub fn process(&self, config: &GlobalConfig) -> WhereResult<SessionCollection> {
-        let label = self.label.clone().unwrap_or(self.endpoint.to_owned());
-        let retries = self.max_retries.unwrap_or(config.max_retries);
-        let address = self.get_address(config)?;
-        let timeout = Duration::from_millis(self.timeout.unwrap_or(config.timeout));
-        let socket = self.create_socket(&address, timeout)?;
-        let buf = [0; MAX_PAYLOAD_LENGTH];
+        if let Some(timeout) = self.timeout {
+            server = server.with_timeout(Duration::from_millis(timeout));
+        }
 
-        for _ in 0..retries {
-            match Self::attempt_fetch(&socket, &address, buf, &label)? {
-                Some(c) => return Ok(c),
-                None => continue
-            };
+        if let Some(max_retries) = self.max_retries {
+            server = server.with_max_retries(max_retries);
         }
 
-        Err(WhereError::TimedOut(self.endpoint.to_string(), address.to_string(), retries, timeout))
+        server
     }
 }
+
+// Responses are in the same order as the servers
+pub fn build_client(config: &GlobalConfig, servers: &[Server]) -> Client {
+    Client::new()
+        .with_port(config.port)
+        .with_timeout(Duration::from_millis(config.timeout))
+        .with_max_retries(config.max_retries)
+        .with_servers(servers.iter().map(Server::to_client_server))
+}
@@ -1,7 +1,105 @@
-use chrono::DateTime;
-use whrd::Session;
+use std::collections::HashSet;
+use chrono::{DateTime, Utc};
+use whrd::{HistoryEntry, HostInfo, Session};
 use crate::config::GlobalConfig;
 
+pub struct HostSummary {
+    pub label: String,
+    pub info: Option<HostInfo>,
+    pub users: usize,
+    pub reachable: bool
+}
+
+impl HostSummary {
+    pub fn new(label: String, info: Option<HostInfo>, sessions: &[Session]) -> Self {
+        Self {
+            label,
+            info,
+            // Like ruptime(1), users with several sessions are only counted once
+            users: sessions.iter()
+                .filter(|s| s.active)
+                .map(|s| s.user.as_str())
+                .collect::<HashSet<_>>()
+                .len(),
+            reachable: true
+        }
+    }
+
+    pub fn unreachable(label: String) -> Self {
+        Self {
+            label,
+            info: None,
+            users: 0,
+            reachable: false
+        }
+    }
+}
+
+fn format_uptime(uptime: u64) -> String {
+    let days = uptime / 86400;
+    let hours = uptime % 86400 / 3600;
+    let minutes = uptime % 3600 / 60;
+
+    if days > 0 {
+        format!("{days}+{hours:02}:{minutes:02}")
+    } else {
+        format!("{hours:2}:{minutes:02}")
+    }
+}
+
+pub fn print_hosts(hosts: Vec<HostSummary>) {
+    let host_padding = hosts.iter()
+        .map(|h| h.label.len())
+        .max()
+        .unwrap_or_default()
+        .max(4);
+
+    println!("{:<pad_0$}  {:<6}  {:<10}  {:<5}  {:<16}  {:<4}  {:<19}  OS",
+             "Host",
+             "Status",
+             "Uptime",
+             "Users",
+             "Load",
+             "CPUs",
+             "Boot",
+             pad_0 = host_padding);
+
+    for host in hosts {
+        if !host.reachable {
+            println!("{:<pad_0$}  down", host.label, pad_0 = host_padding);
+            continue;
+        }
+
+        match host.info {
+            Some(info) => {
+                let [one, five, fifteen] = info.load_average_f64();
+                let load = format!("{one:.2}, {five:.2}, {fifteen:.2}");
+                let boot = format_time(info.boot_time);
+
+                println!("{:<pad_0$}  {:<6}  {:<10}  {:<5}  {:<16}  {:<4}  {:<19}  {}",
+                         host.label,
+                         "up",
+                         format_uptime(info.uptime),
+                         host.users,
+                         load,
+                         info.cpus,
+                         boot,
+                         info.os,
+                         pad_0 = host_padding);
+            }
+            None => {
+                // Servers that do not send host information can still tell us about their users
+                println!("{:<pad_0$}  {:<6}  {:<10}  {}",
+                         host.label,
+                         "up",
+                         "?",
+                         host.users,
+                         pad_0 = host_padding);
+            }
+        }
+    }
+}
+
 pub fn print_summary(mut sessions: Vec<Session>, config: GlobalConfig) {
     fn max_key_with_min<T, F>(sessions: &[Session], get_key: F, floor: T) -> T
         where
@@ -26,10 +124,20 @@ pub fn print_summary(mut sessions: Vec<Session>, config: GlobalConfig) {
     let tty_padding = max_key_with_min(&sessions, |s| s.tty.len(), 4);
     let pid_padding = max_key_with_min(&sessions, |s| s.pid.abs().checked_ilog10().unwrap_or_default() + 1 + (s.pid < 0) as u32, 4);
 
+    // The container column is only shown when at least one server reports containers
+    let show_containers = sessions.iter().any(|s| s.container.is_some());
+    let container_padding = max_key_with_min(&sessions, |s| s.container.as_deref().map_or(0, |str| str.len()), 9);
+    let container_column = |container: &str| if show_containers {
+        format!("{:<pad$}  ", container, pad = container_padding)
+    } else {
+        String::new()
+    };
+
     if config.include_inactive {
-        println!("{:pad_0$}  {:<pad_1$}  {:<pad_2$}  {:<pad_3$}  {:<pad_4$}  {:<pad_5$}  Since",
+        println!("{:pad_0$}  {:<pad_1$}  {}{:<pad_2$}  {:<pad_3$}  {:<pad_4$}  {:<pad_5$}  Since",
                  "Act",
                  "Host",
+                 container_column("Container"),
                  "Source",
                  "User",
                  "TTY",
@@ -41,8 +149,9 @@ pub fn print_summary(mut sessions: Vec<Session>, config: GlobalConfig) {
                  pad_4 = tty_padding,
                  pad_5 = pid_padding as usize);
     } else {
-        println!("{:<pad_1$}  {:<pad_2$}  {:<pad_3$}  {:<pad_4$}  {:<pad_5$}  Since",
+        println!("{:<pad_1$}  {}{:<pad_2$}  {:<pad_3$}  {:<pad_4$}  {:<pad_5$}  Since",
                  "Host",
+                 container_column("Container"),
                  "Source",
                  "User",
                  "TTY",
@@ -67,14 +176,15 @@ pub fn print_summary(mut sessions: Vec<Session>, config: GlobalConfig) {
 
         let host = session.host.unwrap_or_else(|| ' '.to_string());
         let remote = session.remote.unwrap_or_else(|| config.source.clone());
+        let container = container_column(session.container.as_deref().unwrap_or_default());
 
-        let datetime = DateTime::from_timestamp(session.login_time, 0).unwrap();
-        let time = datetime.format("%Y-%m-%d %H:%M:%S");
+        let time = format_time(session.login_time);
 
         if config.include_inactive {
-            println!(" {:<pad_0$}  {:<pad_1$}  {:<pad_2$}  {:<pad_3$}  {:<pad_4$}  {:<pad_5$}  {}",
+            println!(" {:<pad_0$}  {:<pad_1$}  {}{:<pad_2$}  {:<pad_3$}  {:<pad_4$}  {:<pad_5$}  {}",
                      active,
                      host,
+                     container,
                      remote,
                      session.user,
                      session.tty,
@@ -87,8 +197,9 @@ pub fn print_summary(mut sessions: Vec<Session>, config: GlobalConfig) {
                      pad_4 = tty_padding,
                      pad_5 = pid_padding as usize);
         } else {
-            println!("{:<pad_1$}  {:<pad_2$}  {:<pad_3$}  {:<pad_4$}  {:<pad_5$}  {}",
+            println!("{:<pad_1$}  {}{:<pad_2$}  {:<pad_3$}  {:<pad_4$}  {:<pad_5$}  {}",
                      host,
+                     container,
                      remote,
                      session.user,
                      session.tty,
@@ -102,3 +213,105 @@ pub fn print_summary(mut sessions: Vec<Session>, config: GlobalConfig) {
         }
     }
 }
+
+// Servers may send any time, so the ones chrono cannot represent are left blank
+fn format_time(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+fn format_duration(seconds: i64) -> String {
+    let days = seconds / 86400;
+    let hours = seconds % 86400 / 3600;
+    let minutes = seconds % 3600 / 60;
+
+    if days > 0 {
+        format!("{days}+{hours:02}:{minutes:02}")
+    } else {
+        format!("{hours:02}:{minutes:02}")
+    }
+}
+
+pub fn print_history(mut entries: Vec<HistoryEntry>, config: GlobalConfig) {
+    let now = Utc::now().timestamp();
+
+    // Most recent logins first, like last(1)
+    entries.sort_by_key(|e| std::cmp::Reverse(e.session.login_time));
+
+    let host_padding = entries.iter().map(|e| e.session.host.as_deref().map_or(0, |str| str.len())).max().unwrap_or_default().max(4);
+    let remote_padding = entries.iter().map(|e| e.session.remote.as_deref().map_or(config.source.len(), |str| str.len())).max().unwrap_or_default().max(6);
+    let username_padding = entries.iter().map(|e| e.session.user.len()).max().unwrap_or_default().max(4);
+    let tty_padding = entries.iter().map(|e| e.session.tty.len()).max().unwrap_or_default().max(3);
+
+    println!("{:<pad_1$}  {:<pad_2$}  {:<pad_3$}  {:<pad_4$}  {:<19}  {:<19}  Duration",
+             "Host",
+             "Source",
+             "User",
+             "TTY",
+             "Login",
+             "Logout",
+             pad_1 = host_padding,
+             pad_2 = remote_padding,
+             pad_3 = username_padding,
+             pad_4 = tty_padding);
+
+    for entry in entries {
+        let duration = format_duration(entry.duration(now));
+        let session = entry.session;
+
+        let host = session.host.unwrap_or_else(|| ' '.to_string());
+        let remote = session.remote.unwrap_or_else(|| config.source.clone());
+
+        let login = format_time(session.login_time);
+        let logout = match entry.logout_time {
+            Some(time) => format_time(time),
+            None => "still logged in".to_string()
+        };
+
+        println!("{:<pad_1$}  {:<pad_2$}  {:<pad_3$}  {:<pad_4$}  {:<19}  {:<19}  {}",
+                 host,
+                 remote,
+                 session.user,
+                 session.tty,
+                 login,
+                 logout,
+                 duration,
+                 pad_1 = host_padding,
+                 pad_2 = remote_padding,
+                 pad_3 = username_padding,
+                 pad_4 = tty_padding);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times_out_of_range_are_blank() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00");
+        assert_eq!(format_time(i64::MAX), "");
+        assert_eq!(format_time(i64::MIN), "");
+    }
+
+    #[test]
+    fn host_users_are_counted_once() {
+        let sessions = [
+            Session::new("alice", "pts/0", 0),
+            Session::new("alice", "pts/1", 0),
+            Session::new("bob", "tty1", 0),
+            Session::new("carol", "pts/2", 0).with_active(false)
+        ];
+
+        assert_eq!(HostSummary::new("server".to_string(), None, &sessions).users, 2);
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(59), "00:00");
+        assert_eq!(format_duration(3 * 3600 + 120), "03:02");
+        assert_eq!(format_duration(2 * 86400 + 3600), "2+01:00");
+        assert!(!format_duration(i64::MAX).is_empty());
+    }
+}
@@ -1,9 +1,26 @@
+use std::path::PathBuf;
 use clap::Parser;
 
 #[derive(Parser, Debug)]
 #[command(name = "whered", version, about)]
 pub struct Args {
-    /// Specify a custom listen address from the default 0.0.0.0:15
+    /// Specify a custom listen address, overriding global.listen_addrs (0.0.0.0:15 by default)
     #[arg(short = 'l', long)]
     pub listen_addr: Option<String>,
+
+    /// Use a different configuration file from the default /etc/whered.toml
+    #[arg(short = 'c', long)]
+    pub config: Option<String>,
+
+    /// Switch to this user once the listening socket is bound
+    #[arg(short = 'u', long)]
+    pub user: Option<String>,
+
+    /// Switch to this group once the listening socket is bound, instead of the user's primary group
+    #[arg(short = 'g', long)]
+    pub group: Option<String>,
+
+    /// Change the root directory to this one once the listening socket is bound
+    #[arg(long)]
+    pub chroot: Option<PathBuf>,
 }
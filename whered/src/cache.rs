@@ -0,0 +1,150 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use whrd::error::WhereResult;
+
+// Keeps what was last built from files, such as the encoded list of sessions,
+// until it expires or the files change
+pub struct SnapshotCache<T: ?Sized> {
+    payload: Option<Arc<T>>,
+    refreshed_at: Instant,
+    ttl: Duration,
+    watcher: Option<Watcher>
+}
+
+impl<T: ?Sized> SnapshotCache<T> {
+    pub fn new(ttl: Duration, watch_paths: Vec<PathBuf>) -> Self {
+        let watcher = if watch_paths.is_empty() {
+            None
+        } else {
+            Watcher::new(watch_paths)
+        };
+
+        Self {
+            payload: None,
+            refreshed_at: Instant::now(),
+            ttl,
+            watcher
+        }
+    }
+
+    pub fn get<F>(&mut self, refresh: F) -> WhereResult<Arc<T>>
+    where
+        F: FnOnce() -> WhereResult<Arc<T>>
+    {
+        // Always drain the watcher so that old events don't pile up
+        let changed = self.watcher.as_mut().is_some_and(|w| w.has_changed());
+        let expired = self.refreshed_at.elapsed() >= self.ttl;
+
+        match &self.payload {
+            Some(payload) if !changed && !expired => Ok(payload.clone()),
+            _ => {
+                // Drop the old payload first, so a failed refresh is retried on the next request
+                self.payload = None;
+                let payload = refresh()?;
+                self.payload = Some(payload.clone());
+                self.refreshed_at = Instant::now();
+
+                Ok(payload)
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+struct Watcher {
+    fd: std::os::fd::OwnedFd,
+    paths: Vec<PathBuf>,
+    // Files replaced instead of being written to lose their watch, which has to be added again
+    needs_rewatch: bool
+}
+
+#[cfg(target_os = "linux")]
+impl Watcher {
+    const EVENTS: u32 = libc::IN_MODIFY | libc::IN_ATTRIB | libc::IN_CLOSE_WRITE | libc::IN_DELETE_SELF | libc::IN_MOVE_SELF;
+
+    fn new(paths: Vec<PathBuf>) -> Option<Self> {
+        use std::os::fd::FromRawFd;
+
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            log::warn!("Unable to watch for session changes: {}", std::io::Error::last_os_error());
+            return None;
+        }
+
+        let mut watcher = Self {
+            fd: unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) },
+            paths,
+            needs_rewatch: false
+        };
+        watcher.add_watches();
+
+        Some(watcher)
+    }
+
+    fn add_watches(&mut self) {
+        use std::os::fd::AsRawFd;
+        use std::os::unix::ffi::OsStrExt;
+
+        self.needs_rewatch = false;
+
+        for path in &self.paths {
+            let Ok(c_path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+                continue;
+            };
+
+            // Missing files are watched again once they show up
+            if unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), c_path.as_ptr(), Self::EVENTS) } < 0 {
+                self.needs_rewatch = true;
+            }
+        }
+    }
+
+    fn has_changed(&mut self) -> bool {
+        use std::os::fd::AsRawFd;
+
+        let mut changed = false;
+        let mut buf = [0u8; 4096];
+
+        loop {
+            let length = unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if length <= 0 {
+                break;
+            }
+
+            changed = true;
+
+            let mut offset = 0;
+            while offset + std::mem::size_of::<libc::inotify_event>() <= length as usize {
+                let event = unsafe { std::ptr::read_unaligned(buf[offset..].as_ptr() as *const libc::inotify_event) };
+
+                if event.mask & (libc::IN_DELETE_SELF | libc::IN_MOVE_SELF | libc::IN_IGNORED) != 0 {
+                    self.needs_rewatch = true;
+                }
+
+                offset += std::mem::size_of::<libc::inotify_event>() + event.len as usize;
+            }
+        }
+
+        if self.needs_rewatch {
+            self.add_watches();
+        }
+
+        changed
+    }
+}
+
+// Other systems only rely on the TTL
+#[cfg(not(target_os = "linux"))]
+struct Watcher;
+
+#[cfg(not(target_os = "linux"))]
+impl Watcher {
+    fn new(_paths: Vec<PathBuf>) -> Option<Self> {
+        None
+    }
+
+    fn has_changed(&mut self) -> bool {
+        false
+    }
+}
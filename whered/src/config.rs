@@ -0,0 +1,173 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use whrd::MAX_REMOTE_LENGTH;
+use crate::args::Args;
+use crate::filter::FilterConfig;
+use crate::logging::LogConfig;
+use crate::metrics::MetricsConfig;
+use crate::privacy::PrivacyConfig;
+use crate::source::{ContainerConfig, SourceConfig};
+
+const CONFIG_PATH: &str = "/etc/whered.toml";
+const LISTEN_ADDR: &str = "0.0.0.0:15";
+const WTMP_PATH: &str = "/var/log/wtmp";
+const CACHE_TTL: u64 = 2000;
+const WORKERS: usize = 4;
+const QUEUE_SIZE: usize = 256;
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Config {
+    pub global: GlobalConfig,
+    pub privacy: PrivacyConfig,
+    pub filter: FilterConfig,
+    pub source: SourceConfig,
+    pub containers: Vec<ContainerConfig>,
+    pub history: HistoryConfig,
+    pub cache: CacheConfig,
+    pub sandbox: SandboxConfig,
+    pub log: LogConfig,
+    pub metrics: MetricsConfig
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct GlobalConfig {
+    pub listen_addrs: Vec<String>,
+    pub hostname: Option<String>,
+    pub workers: usize,
+    pub queue_size: usize
+}
+
+impl Default for GlobalConfig {
+    fn default() -> Self {
+        Self {
+            listen_addrs: vec![LISTEN_ADDR.to_string()],
+            hostname: None,
+            workers: WORKERS,
+            queue_size: QUEUE_SIZE
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    pub path: PathBuf
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from(WTMP_PATH)
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub ttl: u64,
+    pub watch: bool
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: CACHE_TTL,
+            watch: true
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct SandboxConfig {
+    pub enabled: bool,
+    pub read_paths: Vec<PathBuf>
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            read_paths: vec![]
+        }
+    }
+}
+
+impl Config {
+    pub fn build(args: &Args) -> Self {
+        // The configuration file is optional at startup unless it was explicitly requested
+        Self::load(args, args.config.is_none()).unwrap_or_else(|e| {
+            eprintln!("whered: {e}");
+            std::process::exit(1);
+        })
+    }
+
+    // Like build, but leaves it to the caller to handle errors. A file missing on
+    // reload is an error, as it may only have moved out of reach, such as after a
+    // chroot, and the defaults are less private than most configurations.
+    pub fn reload(args: &Args) -> Result<Self, String> {
+        Self::load(args, false)
+    }
+
+    fn load(args: &Args, optional: bool) -> Result<Self, String> {
+        let path = Self::path(args);
+
+        let config: Self = match fs::read_to_string(path) {
+            Ok(str) => toml::from_str(&str).map_err(|e| format!("Failed to parse configuration file: {e}"))?,
+            Err(e) if e.kind() == ErrorKind::NotFound && optional => Self::default(),
+            Err(e) => return Err(format!("Failed to read configuration file {}: {e}", path.display()))
+        };
+
+        config.validate().map_err(|e| format!("Invalid configuration file: {e}"))?;
+
+        // Users are looked up for each session, and the user database is usually not
+        // there in the new root
+        if args.chroot.is_some() && config.filter.needs_accounts() {
+            return Err("Invalid configuration file: filter rules on uids and groups can't be used with --chroot".to_string());
+        }
+
+        Ok(config)
+    }
+
+    pub fn path(args: &Args) -> &Path {
+        Path::new(args.config.as_deref().unwrap_or(CONFIG_PATH))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.global.listen_addrs.is_empty() {
+            return Err("global.listen_addrs must not be empty".to_string());
+        }
+
+        for addr in &self.global.listen_addrs {
+            addr.parse::<SocketAddr>().map_err(|_| format!("Invalid address in global.listen_addrs: {addr}"))?;
+        }
+
+        if self.global.workers == 0 || self.global.queue_size == 0 {
+            return Err("global.workers and global.queue_size must be at least 1".to_string());
+        }
+
+        if let Some(hostname) = &self.global.hostname {
+            if hostname.len() > MAX_REMOTE_LENGTH {
+                return Err(format!("global.hostname is longer than {MAX_REMOTE_LENGTH} bytes"));
+            }
+        }
+
+        self.privacy.validate()?;
+        self.source.validate()?;
+        self.metrics.validate()?;
+
+        for container in &self.containers {
+            container.validate()?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+use std::cell::OnceCell;
+use serde::Deserialize;
+use whrd::Session;
+use crate::users::{self, Account};
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct FilterConfig {
+    pub include: FilterRules,
+    pub exclude: FilterRules
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct FilterRules {
+    pub users: Vec<String>,
+    pub uids: Vec<UidRange>,
+    pub groups: Vec<Group>,
+    pub ttys: Vec<String>
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(try_from = "UidRangeValue")]
+pub struct UidRange {
+    start: libc::uid_t,
+    end: libc::uid_t
+}
+
+// Resolved when the configuration is loaded, so that requests don't depend on
+// the group database
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "String")]
+pub struct Group {
+    gid: libc::gid_t
+}
+
+impl TryFrom<String> for Group {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        match users::group_id(&name) {
+            Some(gid) => Ok(Self { gid }),
+            None => Err(format!("Unknown group in filter rules: {name}"))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum UidRangeValue {
+    Single(libc::uid_t),
+    Range(String)
+}
+
+impl TryFrom<UidRangeValue> for UidRange {
+    type Error = String;
+
+    fn try_from(value: UidRangeValue) -> Result<Self, Self::Error> {
+        let parse = |s: &str| s.trim().parse::<libc::uid_t>().map_err(|e| format!("Invalid UID {s:?}: {e}"));
+
+        let (start, end) = match value {
+            UidRangeValue::Single(uid) => (uid, uid),
+            UidRangeValue::Range(range) => match range.split_once('-') {
+                Some((start, end)) => (parse(start)?, parse(end)?),
+                None => {
+                    let uid = parse(&range)?;
+                    (uid, uid)
+                }
+            }
+        };
+
+        if start > end {
+            Err(format!("Invalid UID range {start}-{end}"))
+        } else {
+            Ok(Self { start, end })
+        }
+    }
+}
+
+impl UidRange {
+    fn contains(&self, uid: libc::uid_t) -> bool {
+        (self.start..=self.end).contains(&uid)
+    }
+}
+
+impl FilterConfig {
+    // Whether sessions have to be looked up in the user database
+    pub fn needs_accounts(&self) -> bool {
+        self.include.needs_accounts() || self.exclude.needs_accounts()
+    }
+
+    pub fn allows(&self, session: &Session) -> bool {
+        let account = OnceCell::new();
+        // Relayed sessions have the host they come from, and belong to users of
+        // that host, so rules on uids and groups are left out for them
+        let local = session.host.is_none();
+
+        // Users that can't be looked up don't match include rules, and are
+        // excluded if an exclude rule would need to look them up
+        let included = self.include.is_empty(local) || self.include.matches(session, local, &account).unwrap_or(false);
+        included && !self.exclude.matches(session, local, &account).unwrap_or(true)
+    }
+}
+
+impl FilterRules {
+    fn is_empty(&self, local: bool) -> bool {
+        self.users.is_empty() && self.ttys.is_empty() && (!local || !self.needs_accounts())
+    }
+
+    fn needs_accounts(&self) -> bool {
+        !self.uids.is_empty() || !self.groups.is_empty()
+    }
+
+    // None if the rules depend on a user that can't be looked up
+    fn matches(&self, session: &Session, local: bool, account: &OnceCell<Option<Account>>) -> Option<bool> {
+        if self.users.contains(&session.user) {
+            return Some(true);
+        }
+
+        if self.ttys.iter().any(|pattern| glob_match(pattern.as_bytes(), session.tty.as_bytes())) {
+            return Some(true);
+        }
+
+        if !local || !self.needs_accounts() {
+            return Some(false);
+        }
+
+        // Only hit the user database when a rule actually needs it
+        let account = account.get_or_init(|| Account::from_name(&session.user)).as_ref()?;
+
+        if self.uids.iter().any(|range| range.contains(account.uid)) {
+            return Some(true);
+        }
+
+        Some(self.groups.iter().any(|group| account.gid == group.gid || account.groups.contains(&group.gid)))
+    }
+}
+
+// Shell-style pattern matching supporting '*' and '?'
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => glob_match(&pattern[1..], text) || (!text.is_empty() && glob_match(pattern, &text[1..])),
+        (Some(b'?'), Some(_)) => glob_match(&pattern[1..], &text[1..]),
+        (Some(p), Some(t)) if p == t => glob_match(&pattern[1..], &text[1..]),
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(config: &str) -> FilterConfig {
+        toml::from_str(config).unwrap()
+    }
+
+    fn session(user: &str, tty: &str) -> Session {
+        Session::new(user, tty, 1_700_000_000)
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_match(b"pts/*", b"pts/0"));
+        assert!(glob_match(b"pts/*", b"pts/"));
+        assert!(!glob_match(b"pts/*", b"tty1"));
+        assert!(glob_match(b"tty?", b"tty1"));
+        assert!(!glob_match(b"tty?", b"tty"));
+        assert!(!glob_match(b"tty?", b"tty12"));
+        assert!(glob_match(b"*1*", b"tty12"));
+        assert!(glob_match(b"console", b"console"));
+        assert!(glob_match(b"*", b""));
+    }
+
+    #[test]
+    fn empty_glob() {
+        assert!(glob_match(b"", b""));
+        assert!(!glob_match(b"", b"pts/0"));
+
+        // Graphical sessions may have no TTY at all
+        let filter = filter("exclude.ttys = [\"\"]");
+        assert!(!filter.allows(&session("alice", "")));
+        assert!(filter.allows(&session("alice", "pts/0")));
+    }
+
+    #[test]
+    fn uid_ranges() {
+        let range = UidRange::try_from(UidRangeValue::Range("1000-2000".to_string())).unwrap();
+        assert!(range.contains(1000));
+        assert!(range.contains(2000));
+        assert!(!range.contains(999));
+        assert!(!range.contains(2001));
+
+        let range = UidRange::try_from(UidRangeValue::Range(" 42 ".to_string())).unwrap();
+        assert!(range.contains(42));
+        assert!(!range.contains(43));
+
+        let range = UidRange::try_from(UidRangeValue::Single(0)).unwrap();
+        assert!(range.contains(0));
+
+        assert!(UidRange::try_from(UidRangeValue::Range("2000-1000".to_string())).is_err());
+        assert!(UidRange::try_from(UidRangeValue::Range("alice".to_string())).is_err());
+        assert!(toml::from_str::<FilterConfig>("include.uids = [\"1000-\"]").is_err());
+    }
+
+    #[test]
+    fn uid_rules() {
+        let filter = filter("include.uids = [0, \"1000-2000\"]");
+
+        assert!(filter.needs_accounts());
+        assert!(filter.allows(&session("root", "pts/0")));
+        // Users that can't be looked up never match include rules
+        assert!(!filter.allows(&session("nonexistent-user", "pts/0")));
+    }
+
+    #[test]
+    fn group_rules() {
+        let error = toml::from_str::<FilterConfig>("include.groups = [\"nonexistent-group\"]").unwrap_err();
+        assert!(error.to_string().contains("Unknown group in filter rules: nonexistent-group"));
+
+        let filter = filter("exclude.groups = [\"root\"]");
+        assert!(!filter.allows(&session("root", "pts/0")));
+        // Users that cannot be looked up may belong to the group, so they are excluded too
+        assert!(!filter.allows(&session("nonexistent-user", "pts/0")));
+    }
+
+    #[test]
+    fn exclude_rules_take_precedence() {
+        let filter = filter(r#"
+            include.users = ["alice"]
+            exclude.ttys = ["pts/*"]
+        "#);
+
+        assert!(filter.allows(&session("alice", "tty1")));
+        assert!(!filter.allows(&session("alice", "pts/0")));
+        assert!(!filter.allows(&session("bob", "tty1")));
+        assert!(!filter.needs_accounts());
+    }
+
+    #[test]
+    fn relayed_sessions() {
+        let relayed = |user: &str, tty: &str| {
+            let mut session = session(user, tty);
+            session.host = Some("db".to_string());
+            session
+        };
+
+        // Users of other hosts are not in the local user database
+        let rules = filter(r#"
+            include.uids = [0]
+            exclude.groups = ["root"]
+        "#);
+        assert!(rules.allows(&relayed("nonexistent-user", "pts/0")));
+        assert!(rules.allows(&relayed("root", "pts/0")));
+        assert!(!rules.allows(&session("root", "pts/0")));
+
+        let rules = filter(r#"
+            include.uids = [0]
+            include.users = ["alice"]
+            exclude.ttys = ["tty*"]
+        "#);
+        assert!(rules.allows(&relayed("alice", "pts/0")));
+        assert!(!rules.allows(&relayed("alice", "tty1")));
+        assert!(!rules.allows(&relayed("root", "pts/0")));
+    }
+
+    #[test]
+    fn no_rules() {
+        let filter = FilterConfig::default();
+
+        assert!(filter.allows(&session("alice", "pts/0")));
+        assert!(!filter.needs_accounts());
+    }
+}
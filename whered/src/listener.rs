@@ -0,0 +1,90 @@
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use whrd::MAX_REQUEST_LENGTH;
+use crate::pool::{Job, WorkerPool};
+use crate::server::State;
+use crate::systemd;
+
+// How long a listener can take to notice it has been stopped
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+// The state used for new requests, replaced when the configuration is reloaded
+pub type SharedState = Arc<RwLock<Arc<State>>>;
+
+pub struct Listener {
+    pub address: Option<SocketAddr>,
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>
+}
+
+impl Listener {
+    pub fn spawn(socket: UdpSocket, pool: &Arc<WorkerPool>, state: &SharedState) -> io::Result<Self> {
+        socket.set_read_timeout(Some(STOP_CHECK_INTERVAL))?;
+
+        let address = socket.local_addr().ok();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let handle = {
+            let socket = Arc::new(socket);
+            let pool = Arc::clone(pool);
+            let state = Arc::clone(state);
+            let stop = Arc::clone(&stop);
+
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    match receive_request(&socket, &pool, &state) {
+                        Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+                        Err(e) => log::warn!("Unable to receive request: {e}"),
+                        Ok(()) => {}
+                    }
+
+                    // systemd restarts the server once requests stop being
+                    // received or answered
+                    if systemd::watchdog_interval().is_some_and(|interval| !pool.is_stalled(interval)) {
+                        systemd::ping_watchdog();
+                    }
+                }
+            })
+        };
+
+        Ok(Self {
+            address,
+            stop,
+            handle
+        })
+    }
+
+    // Requests that were already received are still answered by the workers
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.handle.join();
+    }
+}
+
+fn receive_request(socket: &Arc<UdpSocket>, pool: &WorkerPool, state: &SharedState) -> io::Result<()> {
+    let mut buf = [0; MAX_REQUEST_LENGTH];
+
+    let (length, src) = socket.recv_from(&mut buf)?;
+    log::debug!(client:% = src, bytes = length; "Received request");
+
+    let state = Arc::clone(&state.read().unwrap_or_else(|e| e.into_inner()));
+
+    let job = Job {
+        socket: Arc::clone(socket),
+        src,
+        request: buf[..length].to_vec(),
+        received_at: Instant::now(),
+        state: Arc::clone(&state)
+    };
+
+    if !pool.submit(job) {
+        state.metrics.record_request("dropped", None);
+        log::warn!(client:% = src, outcome = "dropped"; "Dropping request, too many requests are waiting");
+    }
+
+    Ok(())
+}
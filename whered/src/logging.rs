@@ -0,0 +1,253 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::sync::Mutex;
+use log::kv::{self, Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+
+const IDENTIFIER: &str = "whered";
+// LOG_DAEMON, see syslog(3)
+const SYSLOG_FACILITY: u8 = 3;
+
+#[cfg(target_vendor = "apple")]
+const SYSLOG_PATH: &str = "/var/run/syslog";
+#[cfg(not(target_vendor = "apple"))]
+const SYSLOG_PATH: &str = "/dev/log";
+const JOURNALD_PATH: &str = "/run/systemd/journal/socket";
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct LogConfig {
+    pub level: LevelFilter,
+    pub sink: LogSink
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::Warn,
+            sink: LogSink::default()
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogSink {
+    #[default]
+    Stderr,
+    Syslog,
+    Journald
+}
+
+// The level is left to log::max_level, so that it can be changed on reload
+struct Logger {
+    sink: Sink
+}
+
+enum Sink {
+    Stderr,
+    Syslog(LogSocket),
+    Journald(LogSocket)
+}
+
+struct LogSocket {
+    path: &'static str,
+    socket: Mutex<UnixDatagram>
+}
+
+// Sockets are connected right away, so that logging keeps working after a chroot
+pub fn init(config: &LogConfig) {
+    let sink = match config.sink {
+        LogSink::Stderr => Ok(Sink::Stderr),
+        LogSink::Syslog => LogSocket::connect(SYSLOG_PATH).map(Sink::Syslog),
+        LogSink::Journald => LogSocket::connect(JOURNALD_PATH).map(Sink::Journald)
+    };
+
+    let sink = sink.unwrap_or_else(|e| {
+        eprintln!("{IDENTIFIER}: Unable to connect to the {:?} log sink, using stderr instead: {e}", config.sink);
+        Sink::Stderr
+    });
+
+    let logger = Logger {
+        sink
+    };
+
+    log::set_max_level(config.level);
+    let _ = log::set_boxed_logger(Box::new(logger));
+}
+
+fn connect(path: &str) -> io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(path)?;
+    Ok(socket)
+}
+
+impl LogSocket {
+    fn connect(path: &'static str) -> io::Result<Self> {
+        Ok(Self {
+            path,
+            socket: Mutex::new(connect(path)?)
+        })
+    }
+
+    fn send(&self, message: &[u8]) -> io::Result<()> {
+        let mut socket = self.socket.lock().unwrap_or_else(|e| e.into_inner());
+
+        if socket.send(message).is_ok() {
+            return Ok(());
+        }
+
+        // The socket stops working when the daemon behind it restarts, so try a
+        // new one. This fails after a chroot, and the message is lost then.
+        *socket = connect(self.path)?;
+        socket.send(message).map(|_| ())
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // Logging must never take the server down, so errors are ignored
+        let _ = match &self.sink {
+            Sink::Stderr => {
+                let line = format!("{IDENTIFIER}: {}: {}{}\n", level_name(record.level()), record.args(), format_pairs(record));
+                io::stderr().write_all(line.as_bytes())
+            }
+            Sink::Syslog(socket) => {
+                let priority = SYSLOG_FACILITY * 8 + severity(record.level());
+                let line = format!("<{priority}>{IDENTIFIER}[{}]: {}{}", std::process::id(), record.args(), format_pairs(record));
+                socket.send(line.as_bytes())
+            }
+            Sink::Journald(socket) => socket.send(&journald_entry(record))
+        };
+    }
+
+    fn flush(&self) {}
+}
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Error => "error",
+        Level::Warn => "warning",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace"
+    }
+}
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7
+    }
+}
+
+// Key-value pairs as " key=value", quoting values that contain spaces
+fn format_pairs(record: &Record) -> String {
+    struct Visitor(String);
+
+    impl<'kvs> VisitSource<'kvs> for Visitor {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+            let value = value.to_string();
+
+            if value.is_empty() || value.contains(char::is_whitespace) {
+                let _ = write!(self.0, " {key}={value:?}");
+            } else {
+                let _ = write!(self.0, " {key}={value}");
+            }
+
+            Ok(())
+        }
+    }
+
+    let mut visitor = Visitor(String::new());
+    let _ = record.key_values().visit(&mut visitor);
+    visitor.0
+}
+
+// Builds a message in the journald native protocol, with key-value pairs as
+// their own fields, see systemd-journald.service(8)
+fn journald_entry(record: &Record) -> Vec<u8> {
+    struct Visitor(Vec<u8>);
+
+    impl<'kvs> VisitSource<'kvs> for Visitor {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+            let key: String = key.as_str()
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+                .collect();
+
+            // Fields starting with an underscore are reserved for journald
+            add_journald_field(&mut self.0, key.trim_start_matches('_'), &value.to_string());
+            Ok(())
+        }
+    }
+
+    let mut visitor = Visitor(vec![]);
+    add_journald_field(&mut visitor.0, "MESSAGE", &record.args().to_string());
+    add_journald_field(&mut visitor.0, "PRIORITY", &severity(record.level()).to_string());
+    add_journald_field(&mut visitor.0, "SYSLOG_IDENTIFIER", IDENTIFIER);
+    let _ = record.key_values().visit(&mut visitor);
+
+    visitor.0
+}
+
+fn add_journald_field(entry: &mut Vec<u8>, key: &str, value: &str) {
+    if key.is_empty() {
+        return;
+    }
+
+    entry.extend(key.as_bytes());
+
+    // Values spanning multiple lines are prefixed with their length instead
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+
+    entry.extend(value.as_bytes());
+    entry.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sockets_reconnect() {
+        let dir = std::env::temp_dir().join(format!("whered-logging-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path: &'static str = dir.join("log").to_str().unwrap().to_string().leak();
+        let mut buf = [0; 16];
+
+        let daemon = UnixDatagram::bind(path).unwrap();
+        let socket = LogSocket::connect(path).unwrap();
+        socket.send(b"first").unwrap();
+        let length = daemon.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..length], b"first");
+
+        // The daemon restarting, with a new socket at the same path
+        drop(daemon);
+        std::fs::remove_file(path).unwrap();
+        let daemon = UnixDatagram::bind(path).unwrap();
+
+        socket.send(b"second").unwrap();
+        let length = daemon.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..length], b"second");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
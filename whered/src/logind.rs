@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::io;
+use zbus::blocking::{connection, Connection, Proxy};
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+use whrd::error::WhereResult;
+use whrd::{Session, SessionSource};
+
+const LOGIND_DESTINATION: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+const SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+type Properties = HashMap<String, OwnedValue>;
+
+pub struct LogindSource {
+    // A custom bus address, such as a private bus running a mock logind
+    address: Option<String>
+}
+
+impl LogindSource {
+    pub fn new(address: Option<String>) -> Self {
+        Self {
+            address
+        }
+    }
+
+    fn connect(&self) -> zbus::Result<Connection> {
+        match &self.address {
+            Some(address) => connection::Builder::address(address.as_str())?.build(),
+            None => Connection::system()
+        }
+    }
+
+    fn list_sessions(&self) -> zbus::Result<Vec<Session>> {
+        let connection = self.connect()?;
+        let manager = Proxy::new(&connection, LOGIND_DESTINATION, LOGIND_PATH, MANAGER_INTERFACE)?;
+
+        let entries: Vec<(String, u32, String, String, OwnedObjectPath)> = manager.call("ListSessions", &())?;
+        let mut sessions = vec![];
+
+        for (id, _, user, seat, path) in entries {
+            let properties = Proxy::new(&connection, LOGIND_DESTINATION, path, PROPERTIES_INTERFACE)?;
+
+            // Sessions can disappear between both calls
+            let Ok(properties) = properties.call::<_, _, Properties>("GetAll", &(SESSION_INTERFACE,)) else {
+                continue;
+            };
+
+            sessions.push(session_from_properties(&id, user, &seat, &properties));
+        }
+
+        Ok(sessions)
+    }
+}
+
+impl SessionSource for LogindSource {
+    fn sessions(&self) -> WhereResult<Vec<Session>> {
+        Ok(self.list_sessions().map_err(io::Error::other)?)
+    }
+}
+
+fn get_property<T>(properties: &Properties, name: &str) -> Option<T>
+where
+    T: TryFrom<OwnedValue>
+{
+    properties.get(name)
+        .and_then(|value| value.try_clone().ok())
+        .and_then(|value| T::try_from(value).ok())
+}
+
+fn get_string(properties: &Properties, name: &str) -> Option<String> {
+    get_property::<String>(properties, name).filter(|s| !s.is_empty())
+}
+
+fn session_from_properties(id: &str, user: String, seat: &str, properties: &Properties) -> Session {
+    // Graphical and non-interactive remote sessions have no TTY, use what
+    // identifies them best instead
+    let tty = get_string(properties, "TTY")
+        .or_else(|| get_string(properties, "Display"))
+        .or_else(|| Some(seat.to_string()).filter(|s| !s.is_empty()))
+        .unwrap_or_else(|| format!("session-{id}"));
+
+    let login_time = get_property::<u64>(properties, "Timestamp").unwrap_or_default() / 1_000_000;
+
+    // Sessions in the background of their seat are "online", and logind tracks
+    // idleness the same way for all sessions, including graphical ones
+    let state = get_string(properties, "State").unwrap_or_default();
+    let idle = get_property::<bool>(properties, "IdleHint").unwrap_or(false);
+
+    let mut session = Session {
+        host: None,
+        pid: get_property::<u32>(properties, "Leader").unwrap_or_default() as i32,
+        login_time: login_time as i64,
+        user: get_string(properties, "Name").unwrap_or(user),
+        tty,
+        remote: get_string(properties, "RemoteHost"),
+        active: state == "active" && !idle,
+        container: None
+    };
+
+    session.truncate_to_limits();
+    session
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::path::PathBuf;
+    use std::process::{Child, Command, Stdio};
+    use zbus::interface;
+    use super::*;
+
+    struct MockManager {
+        sessions: Vec<(String, u32, String, String, OwnedObjectPath)>
+    }
+
+    #[interface(name = "org.freedesktop.login1.Manager")]
+    impl MockManager {
+        fn list_sessions(&self) -> Vec<(String, u32, String, String, OwnedObjectPath)> {
+            self.sessions.clone()
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct MockSession {
+        tty: String,
+        display: String,
+        remote_host: String,
+        state: String,
+        idle_hint: bool
+    }
+
+    #[interface(name = "org.freedesktop.login1.Session")]
+    impl MockSession {
+        #[zbus(property, name = "Name")]
+        fn name(&self) -> String {
+            "alice".to_string()
+        }
+
+        #[zbus(property, name = "TTY")]
+        fn tty(&self) -> String {
+            self.tty.clone()
+        }
+
+        #[zbus(property, name = "Display")]
+        fn display(&self) -> String {
+            self.display.clone()
+        }
+
+        #[zbus(property, name = "RemoteHost")]
+        fn remote_host(&self) -> String {
+            self.remote_host.clone()
+        }
+
+        #[zbus(property, name = "State")]
+        fn state(&self) -> String {
+            self.state.clone()
+        }
+
+        #[zbus(property, name = "IdleHint")]
+        fn idle_hint(&self) -> bool {
+            self.idle_hint
+        }
+
+        #[zbus(property, name = "Leader")]
+        fn leader(&self) -> u32 {
+            4242
+        }
+
+        #[zbus(property, name = "Timestamp")]
+        fn timestamp(&self) -> u64 {
+            1_700_000_000_000_000
+        }
+    }
+
+    // A private bus, stopped when dropped
+    struct MockBus {
+        daemon: Child,
+        dir: PathBuf,
+        address: String
+    }
+
+    impl MockBus {
+        fn start() -> Option<Self> {
+            let dir = std::env::temp_dir().join(format!("whered-logind-test-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).ok()?;
+
+            let config = dir.join("bus.conf");
+            std::fs::write(&config, format!(r#"<busconfig>
+  <type>session</type>
+  <listen>unix:path={}</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow send_destination="*"/>
+    <allow own="*"/>
+    <allow receive_sender="*"/>
+  </policy>
+</busconfig>"#, dir.join("bus").display())).ok()?;
+
+            let mut daemon = Command::new("dbus-daemon")
+                .arg(format!("--config-file={}", config.display()))
+                .args(["--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+
+            // The address is printed once the bus is ready
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take()?).read_line(&mut address).ok()?;
+
+            let bus = Self {
+                daemon,
+                dir,
+                address: address.trim().to_string()
+            };
+
+            (!bus.address.is_empty()).then_some(bus)
+        }
+    }
+
+    impl Drop for MockBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn sessions_from_mock_bus() {
+        let Some(bus) = MockBus::start() else {
+            eprintln!("Skipping, dbus-daemon is not available");
+            return;
+        };
+
+        let mock_sessions = [
+            ("1", "seat0", MockSession { tty: "tty2".into(), state: "active".into(), ..Default::default() }),
+            ("2", "seat0", MockSession { display: ":0".into(), state: "online".into(), ..Default::default() }),
+            ("3", "", MockSession { remote_host: "10.0.0.5".into(), state: "active".into(), idle_hint: true, ..Default::default() }),
+            ("4", "seat1", MockSession { state: "closing".into(), ..Default::default() })
+        ];
+
+        let path = |id: &str| OwnedObjectPath::try_from(format!("/org/freedesktop/login1/session/_3{id}")).unwrap();
+        let manager = MockManager {
+            sessions: mock_sessions.iter()
+                .map(|(id, seat, _)| (id.to_string(), 1000, "alice".to_string(), seat.to_string(), path(id)))
+                .collect()
+        };
+
+        let mut builder = connection::Builder::address(bus.address.as_str()).unwrap()
+            .name(LOGIND_DESTINATION).unwrap()
+            .serve_at(LOGIND_PATH, manager).unwrap();
+
+        for (id, _, session) in &mock_sessions {
+            builder = builder.serve_at(path(id), session.clone()).unwrap();
+        }
+
+        let _service = builder.build().unwrap();
+
+        let sessions = LogindSource::new(Some(bus.address.clone())).sessions().ok().unwrap();
+        let summary: Vec<_> = sessions.iter()
+            .map(|s| (s.tty.as_str(), s.active, s.remote.as_deref()))
+            .collect();
+
+        assert_eq!(summary, [
+            ("tty2", true, None),
+            (":0", false, None),
+            ("session-3", false, Some("10.0.0.5")),
+            ("seat1", false, None)
+        ]);
+
+        assert!(sessions.iter().all(|s| s.user == "alice" && s.pid == 4242 && s.login_time == 1_700_000_000));
+    }
+}
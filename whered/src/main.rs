@@ -1,55 +1,219 @@
 mod args;
+mod cache;
+mod config;
+mod filter;
+mod listener;
+mod logging;
+#[cfg(all(target_os = "linux", feature = "logind"))]
+mod logind;
+mod metrics;
+mod pool;
+mod privacy;
+mod relay;
+mod sandbox;
+mod server;
+mod signals;
+mod source;
+mod systemd;
+mod users;
 
 use args::Args;
-use std::net::{SocketAddr, UdpSocket};
+use config::Config;
+use listener::{Listener, SharedState};
+use metrics::Metrics;
+use pool::WorkerPool;
+use sandbox::Privileges;
+use server::State;
+use signals::{Signal, Signals};
+use std::net::{SocketAddr, TcpListener, UdpSocket};
 use std::process;
-use std::str::FromStr;
+use std::sync::{Arc, RwLock};
 use clap::Parser;
-use whrd::error::{WhereError, WhereResult};
-use whrd::{SessionCollection, WHERED_MAGIC};
+use whrd::error::{Report, WhereError, WhereResult};
 
 fn main() {
     let args = Args::parse();
-    let listen_addr = args.listen_addr.unwrap_or(String::from("0.0.0.0:15"));
+    let config = Config::build(&args);
+    logging::init(&config.log);
+    let privileges = Privileges::from_args(&args).unwrap_or_else(|e| {
+        log::error!("{e}");
+        process::exit(1);
+    });
 
-    if let Err(e) = run_server(&listen_addr) {
-        eprintln!("whered: {}", e);
+    if let Err(e) = run_server(&args, config, privileges) {
+        log::error!("{}", Report(&e));
         process::exit(1);
     }
 }
 
-fn run_server(listen_addr: &str) -> WhereResult<()> {
-    let socket_addr_result = SocketAddr::from_str(listen_addr);
+fn run_server(args: &Args, config: Config, privileges: Privileges) -> WhereResult<()> {
+    let signals = Signals::block()?;
 
-    match socket_addr_result {
-        Ok(socket_addr) => {
-            let socket = UdpSocket::bind(socket_addr)?;
-            println!("Now listening on {} port {}/udp", socket_addr.ip(), socket_addr.port());
+    // Listeners can't be changed on reload when they come from systemd or the command line
+    let systemd_sockets = systemd::listen_fds();
+    let fixed_listeners = !systemd_sockets.is_empty() || args.listen_addr.is_some();
 
-            loop {
-                if let Err(e) = handle_request(&socket) {
-                    eprintln!("whered: {}", e);
-                }
-            }
+    let sockets = if systemd_sockets.is_empty() {
+        bind_sockets(&listen_addrs(args, &config))?
+    } else {
+        systemd_sockets
+    };
+
+    let metrics_listener = match &config.metrics.listen_addr {
+        Some(addr) => {
+            let listener = TcpListener::bind(addr)?;
+            log::info!(address = addr.as_str(); "Serving metrics over HTTP");
+            Some(listener)
         }
+        None => None
+    };
+
+    // Everything below only needs the sockets that were just bound
+    systemd::connect_notify_socket();
+    privileges.apply()?;
+    sandbox::restrict(&config, Config::path(args))?;
+
+    systemd::enable_watchdog();
+    let pool = Arc::new(WorkerPool::new(config.global.workers, config.global.queue_size));
+    let metrics = Arc::new(Metrics::default());
+    let state: SharedState = Arc::new(RwLock::new(Arc::new(State::new(config, Arc::clone(&metrics)))));
+
+    if let Some(listener) = metrics_listener {
+        metrics::serve(listener, metrics);
+    }
+
+    let mut listeners = sockets.into_iter()
+        .map(|socket| Listener::spawn(socket, &pool, &state))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for address in listeners.iter().filter_map(|l| l.address) {
+        log::info!(address:% = address; "Listening");
+    }
+
+    systemd::notify("READY=1");
+
+    // Anything but a reload stops the server
+    while let Signal::Reload = signals.wait()? {
+        systemd::notify("RELOADING=1");
+        reload(args, &state, &mut listeners, fixed_listeners, &pool);
+        systemd::notify("READY=1");
+    }
+
+    systemd::notify("STOPPING=1");
+    log::info!("Shutting down");
+
+    for listener in listeners {
+        listener.stop();
+    }
+
+    // The listeners held the only other references to the pool
+    if let Ok(pool) = Arc::try_unwrap(pool) {
+        pool.join();
+    }
+
+    Ok(())
+}
+
+fn reload(args: &Args, state: &SharedState, listeners: &mut Vec<Listener>, fixed_listeners: bool, pool: &Arc<WorkerPool>) {
+    let config = match Config::reload(args) {
+        Ok(config) => config,
         Err(e) => {
-            eprintln!("whered: {}", WhereError::from(e));
-            process::exit(1);
+            log::error!("Keeping the current configuration: {e}");
+            return;
         }
+    };
+
+    let current = Arc::clone(&state.read().unwrap_or_else(|e| e.into_inner()));
+    let old = &current.config;
+
+    if config.global.workers != old.global.workers
+        || config.global.queue_size != old.global.queue_size
+        || config.metrics.listen_addr != old.metrics.listen_addr
+        || config.log.sink != old.log.sink
+    {
+        log::warn!("Changes to global.workers, global.queue_size, metrics.listen_addr and log.sink require a restart");
     }
+
+    if fixed_listeners {
+        if config.global.listen_addrs != old.global.listen_addrs {
+            log::warn!("Ignoring changes to global.listen_addrs, the listen addresses were given by systemd or on the command line");
+        }
+    } else {
+        rebind(&listen_addrs(args, &config), listeners, pool, state);
+    }
+
+    log::set_max_level(config.log.level);
+    *state.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(State::new(config, Arc::clone(&current.metrics)));
+
+    log::info!("Reloaded the configuration");
 }
 
-fn handle_request(socket: &UdpSocket) -> WhereResult<()> {
-    let mut buf = [0; WHERED_MAGIC.len()];
+// Listeners whose address didn't change are kept as they are, so that they don't
+// miss any request. Old listeners are only stopped once every new one is bound,
+// so that a failure leaves the server listening where it was.
+fn rebind(addresses: &[SocketAddr], listeners: &mut Vec<Listener>, pool: &Arc<WorkerPool>, state: &SharedState) {
+    let mut added = vec![];
 
-    let (_, src) = socket.recv_from(&mut buf)?;
-    println!("{src}: New client!");
+    for address in addresses {
+        if listeners.iter().any(|l| l.address == Some(*address)) {
+            continue;
+        }
 
-    let sessions = SessionCollection::fetch();
-    let buf = sessions.to_udp_payload()?;
+        let listener = UdpSocket::bind(address)
+            .and_then(|socket| Listener::spawn(socket, pool, state));
 
-    socket.send_to(&buf, src)?;
-    println!("{src}: Completed request within {} bytes", buf.len());
+        match listener {
+            Ok(listener) => added.push(listener),
+            Err(e) => {
+                log::error!(address:% = address; "Unable to listen, keeping the current listeners: {e}");
 
-    Ok(())
+                for listener in added {
+                    listener.stop();
+                }
+
+                return;
+            }
+        }
+    }
+
+    let (kept, removed): (Vec<_>, Vec<_>) = std::mem::take(listeners)
+        .into_iter()
+        .partition(|l| l.address.is_some_and(|a| addresses.contains(&a)));
+    *listeners = kept;
+
+    for listener in removed {
+        if let Some(address) = listener.address {
+            log::info!(address:% = address; "No longer listening");
+        }
+
+        listener.stop();
+    }
+
+    for listener in added {
+        if let Some(address) = listener.address {
+            log::info!(address:% = address; "Listening");
+        }
+
+        listeners.push(listener);
+    }
+}
+
+fn listen_addrs(args: &Args, config: &Config) -> Vec<SocketAddr> {
+    let addrs = match &args.listen_addr {
+        Some(addr) => vec![addr.clone()],
+        None => config.global.listen_addrs.clone()
+    };
+
+    addrs.iter()
+        .map(|addr| addr.parse().unwrap_or_else(|e| {
+            log::error!("{}", Report(&WhereError::from(e)));
+            process::exit(1);
+        }))
+        .collect()
+}
+
+fn bind_sockets(addresses: &[SocketAddr]) -> WhereResult<Vec<UdpSocket>> {
+    Ok(addresses.iter()
+        .map(UdpSocket::bind)
+        .collect::<Result<Vec<_>, _>>()?)
 }
@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use serde::Deserialize;
+use metrics_http::escape_label;
+
+// Upper bounds of the histogram buckets
+const PAYLOAD_BUCKETS: [f64; 7] = [128.0, 512.0, 1024.0, 4096.0, 16384.0, 32768.0, 65536.0];
+const FETCH_BUCKETS: [f64; 9] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5];
+
+const OUTCOMES: [&str; 4] = ["answered", "ignored", "failed", "dropped"];
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub listen_addr: Option<String>,
+    pub per_user: bool
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            listen_addr: None,
+            per_user: true
+        }
+    }
+}
+
+impl MetricsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.listen_addr {
+            Some(addr) if addr.parse::<std::net::SocketAddr>().is_err() => Err(format!("Invalid metrics.listen_addr: {addr}")),
+            _ => Ok(())
+        }
+    }
+}
+
+// Kept outside of the server state, so that counters survive configuration reloads
+#[derive(Default)]
+pub struct Metrics {
+    inner: Mutex<MetricsData>
+}
+
+#[derive(Default)]
+struct MetricsData {
+    requests: [u64; OUTCOMES.len()],
+    encode_errors: u64,
+    payload_sizes: Histogram<{ PAYLOAD_BUCKETS.len() }>,
+    fetch_durations: Histogram<{ FETCH_BUCKETS.len() }>,
+    active_sessions: u64,
+    inactive_sessions: u64,
+    user_sessions: BTreeMap<String, u64>,
+    // By label, for relays
+    upstreams: BTreeMap<String, UpstreamMetrics>
+}
+
+#[derive(Default)]
+struct UpstreamMetrics {
+    up: bool,
+    failures: u64
+}
+
+struct Histogram<const N: usize> {
+    counts: [u64; N],
+    count: u64,
+    sum: f64
+}
+
+impl<const N: usize> Default for Histogram<N> {
+    fn default() -> Self {
+        Self {
+            counts: [0; N],
+            count: 0,
+            sum: 0.0
+        }
+    }
+}
+
+impl<const N: usize> Histogram<N> {
+    fn observe(&mut self, bounds: &[f64; N], value: f64) {
+        for (count, bound) in self.counts.iter_mut().zip(bounds) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+
+        self.count += 1;
+        self.sum += value;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str, bounds: &[f64; N]) {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+
+        for (count, bound) in self.counts.iter().zip(bounds) {
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {count}");
+        }
+
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", self.count);
+        let _ = writeln!(out, "{name}_sum {}", self.sum);
+        let _ = writeln!(out, "{name}_count {}", self.count);
+    }
+}
+
+// Sessions that were last sent to clients, after filtering and privacy settings
+pub struct SessionCounts {
+    pub active: u64,
+    pub inactive: u64,
+    pub users: BTreeMap<String, u64>
+}
+
+impl Metrics {
+    fn data(&self) -> std::sync::MutexGuard<'_, MetricsData> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn record_request(&self, outcome: &str, bytes: Option<usize>) {
+        let mut data = self.data();
+
+        if let Some(index) = OUTCOMES.iter().position(|o| *o == outcome) {
+            data.requests[index] += 1;
+        }
+
+        if let Some(bytes) = bytes {
+            data.payload_sizes.observe(&PAYLOAD_BUCKETS, bytes as f64);
+        }
+    }
+
+    pub fn record_encode_error(&self) {
+        self.data().encode_errors += 1;
+    }
+
+    pub fn record_fetch(&self, duration: Duration, sessions: SessionCounts) {
+        let mut data = self.data();
+
+        data.fetch_durations.observe(&FETCH_BUCKETS, duration.as_secs_f64());
+        data.active_sessions = sessions.active;
+        data.inactive_sessions = sessions.inactive;
+        data.user_sessions = sessions.users;
+    }
+
+    pub fn record_upstream(&self, label: &str, up: bool) {
+        let mut data = self.data();
+        let upstream = data.upstreams.entry(label.to_string()).or_default();
+
+        upstream.up = up;
+        if !up {
+            upstream.failures += 1;
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let data = self.data();
+        let mut out = String::new();
+
+        out.push_str("# HELP whered_requests_total Requests received, by outcome.\n");
+        out.push_str("# TYPE whered_requests_total counter\n");
+        for (outcome, count) in OUTCOMES.iter().zip(data.requests) {
+            let _ = writeln!(out, "whered_requests_total{{outcome=\"{outcome}\"}} {count}");
+        }
+
+        out.push_str("# HELP whered_encode_errors_total Responses that could not be encoded.\n");
+        out.push_str("# TYPE whered_encode_errors_total counter\n");
+        let _ = writeln!(out, "whered_encode_errors_total {}", data.encode_errors);
+
+        data.payload_sizes.render(&mut out, "whered_response_size_bytes", "Size of the responses sent.", &PAYLOAD_BUCKETS);
+        data.fetch_durations.render(&mut out, "whered_fetch_duration_seconds", "Time taken to read and encode the list of sessions.", &FETCH_BUCKETS);
+
+        out.push_str("# HELP whered_sessions Sessions reported to clients, by state.\n");
+        out.push_str("# TYPE whered_sessions gauge\n");
+        let _ = writeln!(out, "whered_sessions{{state=\"active\"}} {}", data.active_sessions);
+        let _ = writeln!(out, "whered_sessions{{state=\"inactive\"}} {}", data.inactive_sessions);
+
+        if !data.user_sessions.is_empty() {
+            out.push_str("# HELP whered_user_sessions Sessions reported to clients, by user.\n");
+            out.push_str("# TYPE whered_user_sessions gauge\n");
+            for (user, count) in &data.user_sessions {
+                let _ = writeln!(out, "whered_user_sessions{{user=\"{}\"}} {count}", escape_label(user));
+            }
+        }
+
+        if !data.upstreams.is_empty() {
+            out.push_str("# HELP whered_upstream_up Whether an upstream server answered the last request of the relay.\n");
+            out.push_str("# TYPE whered_upstream_up gauge\n");
+            for (label, upstream) in &data.upstreams {
+                let _ = writeln!(out, "whered_upstream_up{{upstream=\"{}\"}} {}", escape_label(label), upstream.up as u8);
+            }
+
+            out.push_str("# HELP whered_upstream_failures_total Requests of the relay that an upstream server did not answer.\n");
+            out.push_str("# TYPE whered_upstream_failures_total counter\n");
+            for (label, upstream) in &data.upstreams {
+                let _ = writeln!(out, "whered_upstream_failures_total{{upstream=\"{}\"}} {}", escape_label(label), upstream.failures);
+            }
+        }
+
+        out
+    }
+}
+
+pub fn serve(listener: TcpListener, metrics: Arc<Metrics>) {
+    thread::spawn(move || {
+        metrics_http::serve(listener, move || metrics.render(), |e| log::debug!("Unable to answer metrics request: {e}"));
+    });
+}
@@ -0,0 +1,179 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use whrd::error::Report;
+use whrd::MAX_PAYLOAD_LENGTH;
+use crate::server::State;
+
+pub struct Job {
+    pub socket: Arc<UdpSocket>,
+    pub src: SocketAddr,
+    pub request: Vec<u8>,
+    pub received_at: Instant,
+    pub state: Arc<State>
+}
+
+pub struct WorkerPool {
+    sender: SyncSender<Job>,
+    workers: Vec<JoinHandle<()>>,
+    progress: Arc<Progress>
+}
+
+// Tells whether the workers are still answering requests
+struct Progress {
+    pending: AtomicUsize,
+    last_answer: Mutex<Instant>
+}
+
+impl WorkerPool {
+    pub fn new(workers: usize, queue_size: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let progress = Arc::new(Progress::default());
+
+        let workers = (0..workers.max(1))
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                let progress = Arc::clone(&progress);
+                thread::spawn(move || run_worker(receiver, progress))
+            })
+            .collect();
+
+        Self {
+            sender,
+            workers,
+            progress
+        }
+    }
+
+    // Returns false when the queue is full, in which case the request is dropped
+    // and the client will retry later
+    pub fn submit(&self, job: Job) -> bool {
+        self.progress.submitted();
+
+        let submitted = self.sender.try_send(job).is_ok();
+        if !submitted {
+            self.progress.done();
+        }
+
+        submitted
+    }
+
+    pub fn is_stalled(&self, limit: Duration) -> bool {
+        self.progress.is_stalled(limit)
+    }
+
+    // Waits for the requests that were already queued to be answered
+    pub fn join(self) {
+        drop(self.sender);
+
+        for worker in self.workers {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self {
+            pending: AtomicUsize::new(0),
+            last_answer: Mutex::new(Instant::now())
+        }
+    }
+}
+
+impl Progress {
+    fn submitted(&self) {
+        // Waiting starts with the first request, not with the last answer
+        if self.pending.fetch_add(1, Ordering::SeqCst) == 0 {
+            *self.last_answer.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+        }
+    }
+
+    fn done(&self) {
+        *self.last_answer.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    // Whether requests have been waiting for longer than the limit without
+    // any of them being answered
+    fn is_stalled(&self, limit: Duration) -> bool {
+        let last_answer = *self.last_answer.lock().unwrap_or_else(|e| e.into_inner());
+        self.pending.load(Ordering::SeqCst) > 0 && last_answer.elapsed() > limit
+    }
+}
+
+fn run_worker(receiver: Arc<Mutex<Receiver<Job>>>, progress: Arc<Progress>) {
+    let mut buffer = Vec::with_capacity(MAX_PAYLOAD_LENGTH);
+
+    loop {
+        let job = {
+            let receiver = receiver.lock().unwrap_or_else(|e| e.into_inner());
+            receiver.recv()
+        };
+
+        let Ok(job) = job else {
+            return;
+        };
+
+        let src = job.src;
+        let result = job.state.handle_request(&job.request, &src, &mut buffer)
+            .and_then(|payload| match payload {
+                Some(payload) => Ok(Some(job.socket.send_to(&payload, src)?)),
+                None => Ok(None)
+            });
+        let duration = job.received_at.elapsed().as_micros() as u64;
+
+        progress.done();
+
+        match result {
+            Ok(Some(bytes)) => {
+                job.state.metrics.record_request("answered", Some(bytes));
+                log::info!(client:% = src, bytes, duration_us = duration, outcome = "answered"; "Answered request");
+            }
+            Ok(None) => {
+                job.state.metrics.record_request("ignored", None);
+                log::info!(client:% = src, duration_us = duration, outcome = "ignored"; "Ignored request");
+            }
+            Err(e) => {
+                job.state.metrics.record_request("failed", None);
+                log::warn!(client:% = src, duration_us = duration, outcome = "failed"; "Failed to answer request: {}", Report(&e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waiting_requests_stall() {
+        let progress = Progress::default();
+        let limit = Duration::from_millis(20);
+
+        // Nothing to answer is never a stall
+        thread::sleep(limit * 2);
+        assert!(!progress.is_stalled(limit));
+
+        progress.submitted();
+        progress.submitted();
+        assert!(!progress.is_stalled(limit));
+
+        thread::sleep(limit * 2);
+        assert!(progress.is_stalled(limit));
+
+        // Answering one of them is progress
+        progress.done();
+        assert!(!progress.is_stalled(limit));
+
+        thread::sleep(limit * 2);
+        assert!(progress.is_stalled(limit));
+
+        progress.done();
+        assert!(!progress.is_stalled(Duration::ZERO));
+    }
+}
@@ -0,0 +1,227 @@
+use std::ffi::CStr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use whrd::{Session, MAX_REMOTE_LENGTH, MAX_USER_TTY_LENGTH};
+
+const MAX_HOST_LENGTH: usize = 1025;
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RemotePolicy {
+    #[default]
+    Full,
+    Hostname,
+    Masked,
+    Omitted
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UserPolicy {
+    #[default]
+    Real,
+    Hashed
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct PrivacyConfig {
+    pub remote: RemotePolicy,
+    pub users: UserPolicy,
+    pub salt: Option<String>,
+    pub zero_pids: bool
+}
+
+impl PrivacyConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.users == UserPolicy::Hashed && self.salt.as_deref().unwrap_or_default().is_empty() {
+            return Err("privacy.salt must be set when privacy.users is \"hashed\"".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn apply(&self, session: &mut Session) {
+        session.remote = match self.remote {
+            RemotePolicy::Full => session.remote.take(),
+            RemotePolicy::Hostname => session.remote.take().and_then(|r| reverse_lookup(&r)),
+            RemotePolicy::Masked => session.remote.take().and_then(|r| mask_address(&r)),
+            RemotePolicy::Omitted => None
+        };
+
+        if self.users == UserPolicy::Hashed {
+            session.user = hash_user(&session.user, self.salt.as_deref().unwrap_or_default());
+        }
+
+        if self.zero_pids {
+            session.pid = 0;
+        }
+    }
+}
+
+fn hash_user(user: &str, salt: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(user.as_bytes());
+
+    let mut hash: String = hasher.finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    hash.truncate(MAX_USER_TTY_LENGTH);
+
+    hash
+}
+
+fn resolve(remote: &str) -> Option<IpAddr> {
+    match remote.parse::<IpAddr>() {
+        Ok(ip) => Some(ip),
+        Err(_) => (remote, 0).to_socket_addrs().ok()?.next().map(|addr| addr.ip())
+    }
+}
+
+// Remote host names are kept as they are, but addresses that cannot be
+// resolved back to a name are dropped instead of being leaked.
+fn reverse_lookup(remote: &str) -> Option<String> {
+    let Ok(ip) = remote.parse::<IpAddr>() else {
+        return Some(remote.to_string());
+    };
+
+    let addr = SocketAddr::new(ip, 0);
+    let (storage, length) = to_sockaddr(&addr);
+    let mut host = [0 as libc::c_char; MAX_HOST_LENGTH];
+
+    let res = unsafe {
+        libc::getnameinfo(
+            &storage as *const libc::sockaddr_storage as *const libc::sockaddr,
+            length,
+            host.as_mut_ptr(),
+            host.len() as libc::socklen_t,
+            std::ptr::null_mut(),
+            0,
+            libc::NI_NAMEREQD
+        )
+    };
+
+    if res != 0 {
+        return None;
+    }
+
+    let name = unsafe { CStr::from_ptr(host.as_ptr()) }.to_string_lossy().into_owned();
+
+    if name.len() > MAX_REMOTE_LENGTH {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+fn mask_address(remote: &str) -> Option<String> {
+    match resolve(remote)? {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            Some(format!("{}/24", Ipv4Addr::new(a, b, c, 0)))
+        }
+        IpAddr::V6(ip) => {
+            let [a, b, c, ..] = ip.segments();
+            Some(format!("{}/48", Ipv6Addr::new(a, b, c, 0, 0, 0, 0, 0)))
+        }
+    }
+}
+
+fn to_sockaddr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+
+    let length = match addr {
+        SocketAddr::V4(v4) => {
+            let sin = unsafe { &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_addr.s_addr = u32::from_ne_bytes(v4.ip().octets());
+            std::mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(v6) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_addr.s6_addr = v6.ip().octets();
+            std::mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+
+    (storage, length as libc::socklen_t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote_session(remote: &str) -> Session {
+        Session::new("alice", "pts/0", 1_700_000_000).with_pid(42).with_remote(remote)
+    }
+
+    fn config(remote: RemotePolicy, users: UserPolicy, salt: Option<&str>) -> PrivacyConfig {
+        PrivacyConfig {
+            remote,
+            users,
+            salt: salt.map(str::to_string),
+            zero_pids: false
+        }
+    }
+
+    #[test]
+    fn masking() {
+        assert_eq!(mask_address("192.168.1.42").as_deref(), Some("192.168.1.0/24"));
+        assert_eq!(mask_address("2001:db8:1234:5678::1").as_deref(), Some("2001:db8:1234::/48"));
+        assert_eq!(mask_address("host.invalid"), None);
+
+        let mut session = remote_session("10.1.2.3");
+        config(RemotePolicy::Masked, UserPolicy::Real, None).apply(&mut session);
+        assert_eq!(session.remote.as_deref(), Some("10.1.2.0/24"));
+    }
+
+    #[test]
+    fn hashing() {
+        // The first half of the SHA-256 of the salt followed by the user name
+        assert_eq!(hash_user("alice", "salt"), "3baa379b47fbc36edfe4f8aa050d10d0");
+        assert_eq!(hash_user("alice", "salt"), hash_user("alice", "salt"));
+        assert_eq!(hash_user("alice", "pepper"), "b1b68da447843a6519d8dd7a9c13c90a");
+        assert_ne!(hash_user("bob", "salt"), hash_user("alice", "salt"));
+
+        let mut session = remote_session("10.1.2.3");
+        config(RemotePolicy::Full, UserPolicy::Hashed, Some("salt")).apply(&mut session);
+        assert_eq!(session.user, "3baa379b47fbc36edfe4f8aa050d10d0");
+        assert_eq!(session.remote.as_deref(), Some("10.1.2.3"));
+    }
+
+    #[test]
+    fn hashing_needs_a_salt() {
+        assert!(config(RemotePolicy::Full, UserPolicy::Hashed, None).validate().is_err());
+        assert!(config(RemotePolicy::Full, UserPolicy::Hashed, Some("")).validate().is_err());
+        assert!(config(RemotePolicy::Full, UserPolicy::Hashed, Some("salt")).validate().is_ok());
+        assert!(config(RemotePolicy::Full, UserPolicy::Real, None).validate().is_ok());
+    }
+
+    #[test]
+    fn reverse_dns() {
+        // Documentation addresses have no name, and must not be leaked instead
+        assert_eq!(reverse_lookup("192.0.2.1"), None);
+        assert_eq!(reverse_lookup("2001:db8::1"), None);
+        assert_eq!(reverse_lookup("host.example.org").as_deref(), Some("host.example.org"));
+
+        let mut session = remote_session("192.0.2.1");
+        config(RemotePolicy::Hostname, UserPolicy::Real, None).apply(&mut session);
+        assert_eq!(session.remote, None);
+    }
+
+    #[test]
+    fn omitted_remotes_and_pids() {
+        let mut session = remote_session("10.1.2.3");
+        let mut privacy = config(RemotePolicy::Omitted, UserPolicy::Real, None);
+        privacy.zero_pids = true;
+        privacy.apply(&mut session);
+
+        assert_eq!(session.remote, None);
+        assert_eq!(session.pid, 0);
+        assert_eq!(session.user, "alice");
+    }
+}
@@ -0,0 +1,269 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::Deserialize;
+use whrd::client::{Client, Server};
+use whrd::error::{Report, WhereResult};
+use whrd::{Session, SessionSource, MAX_REMOTE_LENGTH};
+use crate::metrics::Metrics;
+
+const TIMEOUT: u64 = 2000;
+const MAX_RETRIES: usize = 3;
+const PORT: u16 = 15;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RelayConfig {
+    pub upstreams: Vec<UpstreamConfig>,
+    pub timeout: u64,
+    pub max_retries: usize,
+    pub port: u16
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            upstreams: vec![],
+            timeout: TIMEOUT,
+            max_retries: MAX_RETRIES,
+            port: PORT
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct UpstreamConfig {
+    pub endpoint: String,
+    #[serde(default)]
+    pub label: Option<String>
+}
+
+impl UpstreamConfig {
+    fn get_label(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.endpoint)
+    }
+}
+
+impl RelayConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.upstreams.is_empty() {
+            return Err("source.upstreams must not be empty".to_string());
+        }
+
+        if self.max_retries == 0 {
+            return Err("source.max_retries must be at least 1".to_string());
+        }
+
+        // Labels are sent to clients as the host of the sessions
+        for upstream in &self.upstreams {
+            if upstream.get_label().len() > MAX_REMOTE_LENGTH {
+                return Err(format!("Upstream labels must be at most {MAX_REMOTE_LENGTH} bytes long: {:?}", upstream.get_label()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Reports the sessions of other whered servers, such as ones only reachable
+// from this host. Upstream servers are asked in the background, each on its
+// own, so that an unreachable one doesn't hold up requests for its whole timeout.
+pub struct RelaySource {
+    upstreams: Vec<Arc<Upstream>>,
+    // How long requests wait for upstream servers that never answered yet
+    first_wait: Duration,
+    created_at: Instant
+}
+
+struct Upstream {
+    label: String,
+    client: Client,
+    metrics: Arc<Metrics>,
+    state: Mutex<UpstreamState>,
+    answered: Condvar
+}
+
+#[derive(Default)]
+struct UpstreamState {
+    // None until the first round is over
+    sessions: Option<Vec<Session>>,
+    reachable: bool,
+    refreshing: bool
+}
+
+impl RelaySource {
+    pub fn new(config: RelayConfig, metrics: Arc<Metrics>) -> Self {
+        let timeout = Duration::from_millis(config.timeout);
+
+        let upstreams: Vec<_> = config.upstreams.into_iter().map(|upstream| {
+            let label = upstream.get_label().to_string();
+            let server = Server::new(upstream.endpoint);
+            let server = match upstream.label {
+                Some(label) => server.with_label(label),
+                None => server
+            };
+
+            let client = Client::new()
+                .with_port(config.port)
+                .with_timeout(timeout)
+                .with_max_retries(config.max_retries)
+                .with_server(server);
+
+            Arc::new(Upstream {
+                label,
+                client,
+                metrics: Arc::clone(&metrics),
+                state: Mutex::default(),
+                answered: Condvar::new()
+            })
+        }).collect();
+
+        // Start right away, so that the first requests are less likely to wait
+        for upstream in &upstreams {
+            Upstream::refresh(upstream);
+        }
+
+        Self {
+            upstreams,
+            first_wait: timeout,
+            created_at: Instant::now()
+        }
+    }
+}
+
+impl Upstream {
+    fn lock(&self) -> MutexGuard<'_, UpstreamState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Starts a new round unless one is already running
+    fn refresh(upstream: &Arc<Self>) {
+        {
+            let mut state = upstream.lock();
+            if state.refreshing {
+                return;
+            }
+
+            state.refreshing = true;
+        }
+
+        let upstream = Arc::clone(upstream);
+
+        thread::spawn(move || {
+            let res = fetch_upstream(&upstream.client);
+            upstream.metrics.record_upstream(&upstream.label, res.is_ok());
+
+            let mut state = upstream.lock();
+            // Only changes are logged, as upstream servers are asked for every refresh
+            let was_reachable = state.reachable || state.sessions.is_none();
+
+            state.sessions = match res {
+                Ok(sessions) => {
+                    if !was_reachable {
+                        log::info!(upstream = upstream.label.as_str(); "Upstream server is reachable again");
+                    }
+
+                    state.reachable = true;
+                    Some(sessions)
+                }
+                Err(e) => {
+                    if was_reachable {
+                        log::warn!(upstream = upstream.label.as_str(); "Unable to read sessions from upstream server, leaving its sessions out: {}", Report(&e));
+                    }
+
+                    // Sessions that may have ended are not reported as if they were still there
+                    state.reachable = false;
+                    Some(vec![])
+                }
+            };
+            state.refreshing = false;
+            upstream.answered.notify_all();
+        });
+    }
+
+    // The sessions of the last round, waiting until the deadline if there was none yet
+    fn sessions(&self, deadline: Instant) -> Vec<Session> {
+        let mut state = self.lock();
+
+        while state.sessions.is_none() {
+            let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+                break;
+            };
+
+            state = self.answered.wait_timeout(state, left).unwrap_or_else(|e| e.into_inner()).0;
+        }
+
+        state.sessions.clone().unwrap_or_default()
+    }
+}
+
+impl SessionSource for RelaySource {
+    // Returns the sessions of the last round and starts a new one, so that the
+    // sessions are at most one round old
+    fn sessions(&self) -> WhereResult<Vec<Session>> {
+        for upstream in &self.upstreams {
+            Upstream::refresh(upstream);
+        }
+
+        // Upstream servers only get one timeout to answer the very first time
+        let deadline = self.created_at + self.first_wait;
+
+        Ok(self.upstreams.iter()
+            .flat_map(|upstream| upstream.sessions(deadline))
+            .collect())
+    }
+}
+
+fn fetch_upstream(client: &Client) -> WhereResult<Vec<Session>> {
+    let mut sessions = vec![];
+
+    for response in client.sessions() {
+        let collection = response.result?;
+
+        let (inner, host_info) = collection.into_parts();
+        let origin = host_info.map_or_else(|| response.label.clone(), |info| info.hostname);
+
+        // Sessions the upstream server relayed itself already have their own host
+        sessions.extend(inner.into_iter().map(|mut session| {
+            if session.host.as_deref() == Some(response.label.as_str()) {
+                session.host = Some(origin.clone());
+            }
+
+            session
+        }));
+    }
+
+    Ok(sessions)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use super::*;
+
+    #[test]
+    fn unreachable_upstreams_are_left_out() {
+        // A port nothing listens on anymore
+        let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        let config = RelayConfig {
+            upstreams: vec![UpstreamConfig {
+                endpoint: format!("127.0.0.1:{port}"),
+                label: Some("db".to_string())
+            }],
+            timeout: 100,
+            max_retries: 1,
+            port: PORT
+        };
+        let metrics = Arc::new(Metrics::default());
+        let source = RelaySource::new(config, Arc::clone(&metrics));
+
+        // Waits for the first round to be over
+        assert!(source.upstreams[0].sessions(Instant::now() + Duration::from_secs(5)).is_empty());
+        assert!(source.sessions().unwrap().is_empty());
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("whered_upstream_up{upstream=\"db\"} 0\n"));
+        assert!(rendered.contains("whered_upstream_failures_total{upstream=\"db\"} "));
+    }
+}
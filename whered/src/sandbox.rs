@@ -0,0 +1,442 @@
+use std::ffi::CString;
+use std::io::{self, ErrorKind};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use crate::args::Args;
+use crate::config::Config;
+use crate::users::{self, Account};
+
+// What whered switches to once its sockets are bound. Names are resolved
+// beforehand, as the user database may not exist inside the new root.
+pub struct Privileges {
+    user: Option<(String, Account)>,
+    group: Option<libc::gid_t>,
+    chroot: Option<PathBuf>
+}
+
+impl Privileges {
+    pub fn from_args(args: &Args) -> io::Result<Self> {
+        let user = match &args.user {
+            Some(name) => {
+                let account = Account::from_name(name)
+                    .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("Unknown user: {name}")))?;
+                Some((name.clone(), account))
+            }
+            None => None
+        };
+
+        let group = match &args.group {
+            Some(name) => Some(users::group_id(name)
+                .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("Unknown group: {name}")))?),
+            None => None
+        };
+
+        Ok(Self {
+            user,
+            group,
+            chroot: args.chroot.clone()
+        })
+    }
+
+    pub fn apply(self) -> io::Result<()> {
+        if let Some(path) = &self.chroot {
+            let c_path = CString::new(path.as_os_str().as_bytes())?;
+
+            if unsafe { libc::chroot(c_path.as_ptr()) } != 0 || unsafe { libc::chdir(c"/".as_ptr()) } != 0 {
+                return Err(with_context(format!("Unable to change root directory to {}", path.display())));
+            }
+        }
+
+        let gid = self.group.or(self.user.as_ref().map(|(_, account)| account.gid));
+
+        if let Some(gid) = gid {
+            // Supplementary groups are only kept when switching to the user's own groups
+            let groups = match &self.user {
+                Some((_, account)) if self.group.is_none() => account.groups.clone(),
+                _ => vec![gid]
+            };
+
+            if unsafe { libc::setgroups(groups.len() as _, groups.as_ptr()) } != 0 || unsafe { libc::setgid(gid) } != 0 {
+                return Err(with_context(format!("Unable to switch to group {gid}")));
+            }
+        }
+
+        if let Some((name, account)) = &self.user {
+            if unsafe { libc::setuid(account.uid) } != 0 {
+                return Err(with_context(format!("Unable to switch to user {name}")));
+            }
+
+            // Make sure root privileges cannot be regained
+            if account.uid != 0 && unsafe { libc::setuid(0) } == 0 {
+                return Err(io::Error::other(format!("Root privileges were kept after switching to user {name}")));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn with_context(message: String) -> io::Error {
+    let error = io::Error::last_os_error();
+    io::Error::new(error.kind(), format!("{message}: {error}"))
+}
+
+// Files that are read regardless of the configuration: the terminals of
+// sessions, the files NSS uses to look users and hosts up along with the
+// libraries it loads, and host information. The files in /etc are replaced
+// rather than written to, so the whole directory is allowed.
+#[cfg(target_os = "linux")]
+const SYSTEM_READ_PATHS: [&str; 10] = [
+    "/dev",
+    "/etc",
+    "/proc/loadavg",
+    "/sys/devices/system/cpu/online",
+    "/lib",
+    "/lib64",
+    "/usr/lib",
+    "/usr/lib64",
+    "/nix/store",
+    "/run/systemd/resolve"
+];
+
+// Restricts whered to reading the files it needs and to the system calls it
+// makes. This must be called before any thread is started, as Landlock rules
+// only apply to the calling thread and the ones it creates afterwards.
+#[cfg(target_os = "linux")]
+pub fn restrict(config: &Config, config_path: &Path) -> io::Result<()> {
+    if !config.sandbox.enabled {
+        return Ok(());
+    }
+
+    match read_paths(config, config_path) {
+        Some(paths) => {
+            restrict_files(paths)?;
+            allow_syscalls()
+        }
+        None => {
+            // The command can be any program, which needs other system calls
+            log::warn!("Not restricting file access and system calls, the session source runs a command");
+            deny_syscalls()
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn read_paths(config: &Config, config_path: &Path) -> Option<Vec<PathBuf>> {
+    let mut paths = config.source.read_paths()?;
+
+    // Needed to reload the configuration
+    paths.push(config_path.to_path_buf());
+
+    for container in &config.containers {
+        paths.extend(container.read_paths());
+    }
+
+    if config.history.enabled {
+        paths.push(config.history.path.clone());
+    }
+
+    paths.extend(config.sandbox.read_paths.iter().cloned());
+
+    // Configuration files are usually replaced by renaming a new one over them,
+    // and wtmp is rotated, so rules on the files themselves would keep pointing
+    // to the old ones
+    let mut paths: Vec<PathBuf> = paths.iter().map(|path| containing_dir(path)).collect();
+    paths.extend(SYSTEM_READ_PATHS.iter().map(PathBuf::from));
+    paths.sort();
+    paths.dedup();
+
+    Some(paths)
+}
+
+#[cfg(target_os = "linux")]
+fn containing_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !path.is_dir() && !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => path.to_path_buf()
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn restrict_files(paths: Vec<PathBuf>) -> io::Result<()> {
+    use landlock::{path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus, ABI};
+
+    let abi = ABI::V5;
+
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))
+        .and_then(|ruleset| ruleset.create())
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(paths, AccessFs::from_read(abi))))
+        .and_then(|ruleset| ruleset.restrict_self())
+        .map_err(|e| io::Error::other(format!("Unable to restrict file access: {e}")))?;
+
+    if status.ruleset == RulesetStatus::NotEnforced {
+        log::warn!("Not restricting file access, Landlock is not supported by this kernel");
+    }
+
+    Ok(())
+}
+
+// System calls made once the sandbox is in place: threads and memory, time,
+// reading files and watching them, UDP, the metrics and D-Bus sockets, logging
+// and waiting for signals
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "riscv64")))]
+const ALLOWED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_read,
+    libc::SYS_readv,
+    libc::SYS_pread64,
+    libc::SYS_write,
+    libc::SYS_writev,
+    libc::SYS_openat,
+    libc::SYS_close,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_lseek,
+    libc::SYS_getdents64,
+    libc::SYS_readlinkat,
+    libc::SYS_faccessat,
+    libc::SYS_faccessat2,
+    libc::SYS_fcntl,
+    libc::SYS_ioctl,
+    libc::SYS_inotify_init1,
+    libc::SYS_inotify_add_watch,
+    libc::SYS_inotify_rm_watch,
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    libc::SYS_clone,
+    libc::SYS_clone3,
+    libc::SYS_set_robust_list,
+    libc::SYS_rseq,
+    libc::SYS_futex,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_sigaltstack,
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_rt_sigtimedwait,
+    libc::SYS_getpid,
+    libc::SYS_gettid,
+    libc::SYS_getuid,
+    libc::SYS_geteuid,
+    libc::SYS_getgid,
+    libc::SYS_getegid,
+    libc::SYS_tgkill,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_nanosleep,
+    libc::SYS_setitimer,
+    libc::SYS_gettimeofday,
+    libc::SYS_getrandom,
+    libc::SYS_uname,
+    libc::SYS_sysinfo,
+    libc::SYS_socket,
+    libc::SYS_socketpair,
+    libc::SYS_connect,
+    libc::SYS_bind,
+    libc::SYS_accept4,
+    libc::SYS_getsockname,
+    libc::SYS_getpeername,
+    libc::SYS_getsockopt,
+    libc::SYS_setsockopt,
+    libc::SYS_sendto,
+    libc::SYS_recvfrom,
+    libc::SYS_sendmsg,
+    libc::SYS_recvmsg,
+    libc::SYS_sendmmsg,
+    libc::SYS_shutdown,
+    libc::SYS_ppoll,
+    libc::SYS_pselect6,
+    libc::SYS_epoll_create1,
+    libc::SYS_epoll_ctl,
+    libc::SYS_epoll_pwait,
+    libc::SYS_eventfd2,
+    libc::SYS_timerfd_create,
+    libc::SYS_timerfd_settime,
+    libc::SYS_pipe2
+];
+
+// Older versions of system calls, which x86_64 still has
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const LEGACY_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_open,
+    libc::SYS_stat,
+    libc::SYS_lstat,
+    libc::SYS_access,
+    libc::SYS_readlink,
+    libc::SYS_poll,
+    libc::SYS_select,
+    libc::SYS_epoll_wait,
+    libc::SYS_accept,
+    libc::SYS_alarm
+];
+
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+const LEGACY_SYSCALLS: &[libc::c_long] = &[];
+
+// System calls that could be used to escalate privileges, escape the sandbox
+// or tamper with the system, denied when the session source runs a command
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "riscv64")))]
+const DENIED_SYSCALLS: [libc::c_long; 38] = [
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_readv,
+    libc::SYS_process_vm_writev,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_open_tree,
+    libc::SYS_move_mount,
+    libc::SYS_fsopen,
+    libc::SYS_fsmount,
+    libc::SYS_kexec_load,
+    libc::SYS_kexec_file_load,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_reboot,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_bpf,
+    libc::SYS_perf_event_open,
+    libc::SYS_userfaultfd,
+    libc::SYS_keyctl,
+    libc::SYS_add_key,
+    libc::SYS_request_key,
+    libc::SYS_settimeofday,
+    libc::SYS_clock_settime,
+    libc::SYS_acct,
+    libc::SYS_personality,
+    libc::SYS_setuid,
+    libc::SYS_setgid,
+    libc::SYS_setreuid,
+    libc::SYS_setregid,
+    libc::SYS_setresuid,
+    libc::SYS_setresgid,
+    libc::SYS_setgroups
+];
+
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "riscv64")))]
+use seccompiler::{SeccompAction, SeccompRule};
+
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "riscv64")))]
+type SyscallRules = std::collections::BTreeMap<libc::c_long, Vec<SeccompRule>>;
+
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "riscv64")))]
+fn allow_syscalls() -> io::Result<()> {
+    use seccompiler::{SeccompCmpArgLen, SeccompCmpOp, SeccompCondition};
+
+    // An empty rule list matches every call to the system call
+    let mut rules: SyscallRules = ALLOWED_SYSCALLS.iter()
+        .chain(LEGACY_SYSCALLS)
+        .map(|syscall| (*syscall, vec![]))
+        .collect();
+
+    // Threads of the D-Bus connection name themselves, and NSS modules may check
+    // capabilities, which is all prctl is needed for
+    let prctl = [libc::PR_SET_NAME, libc::PR_GET_NAME, libc::PR_CAPBSET_READ].into_iter()
+        .map(|option| SeccompCondition::new(0, SeccompCmpArgLen::Dword, SeccompCmpOp::Eq, option as u64)
+            .and_then(|condition| SeccompRule::new(vec![condition])))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| io::Error::other(format!("Unable to build system call filter: {e}")))?;
+    rules.insert(libc::SYS_prctl, prctl);
+
+    apply_filter(rules, SeccompAction::Errno(libc::EPERM as u32), SeccompAction::Allow)
+}
+
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "riscv64")))]
+fn deny_syscalls() -> io::Result<()> {
+    let rules = DENIED_SYSCALLS.iter()
+        .map(|syscall| (*syscall, vec![]))
+        .collect();
+
+    apply_filter(rules, SeccompAction::Allow, SeccompAction::Errno(libc::EPERM as u32))
+}
+
+// Applies the first action to the system calls that don't match the rules, and
+// the second one to the ones that do
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "riscv64")))]
+fn apply_filter(rules: SyscallRules, default: SeccompAction, matched: SeccompAction) -> io::Result<()> {
+    use seccompiler::{BpfProgram, SeccompFilter, TargetArch};
+
+    let arch = TargetArch::try_from(std::env::consts::ARCH)
+        .map_err(|e| io::Error::other(format!("Unable to build system call filter: {e}")))?;
+
+    let filter = SeccompFilter::new(rules, default, matched, arch)
+        .and_then(BpfProgram::try_from)
+        .map_err(|e| io::Error::other(format!("Unable to build system call filter: {e}")))?;
+
+    seccompiler::apply_filter_all_threads(&filter)
+        .map_err(|e| io::Error::other(format!("Unable to filter system calls: {e}")))
+}
+
+#[cfg(all(target_os = "linux", not(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "riscv64"))))]
+fn allow_syscalls() -> io::Result<()> {
+    log::warn!("Not filtering system calls, this architecture is not supported");
+    Ok(())
+}
+
+#[cfg(all(target_os = "linux", not(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "riscv64"))))]
+fn deny_syscalls() -> io::Result<()> {
+    log::warn!("Not filtering system calls, this architecture is not supported");
+    Ok(())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::sync::mpsc;
+    use crate::source::SourceConfig;
+    use super::*;
+
+    #[test]
+    fn files_replaced_by_renaming_can_be_read() {
+        let dir = std::env::temp_dir().join(format!("whered-sandbox-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (config_path, sessions_path) = (dir.join("whered.toml"), dir.join("sessions.json"));
+        std::fs::write(&config_path, "old").unwrap();
+        std::fs::write(&sessions_path, "old").unwrap();
+
+        let config = Config {
+            source: SourceConfig::Json { path: sessions_path.clone() },
+            ..Config::default()
+        };
+        let paths = read_paths(&config, &config_path).unwrap();
+        assert!(paths.contains(&dir));
+        assert!(!paths.contains(&config_path));
+
+        // Landlock only restricts the calling thread, and the files are replaced
+        // from outside of it, as another process would
+        let (restricted_tx, restricted_rx) = mpsc::channel();
+        let (replaced_tx, replaced_rx) = mpsc::channel();
+        let reader = std::thread::spawn(move || {
+            restrict_files(paths).unwrap();
+            restricted_tx.send(()).unwrap();
+            replaced_rx.recv().unwrap();
+
+            (std::fs::read_to_string(config_path), std::fs::read_to_string(sessions_path))
+        });
+
+        restricted_rx.recv().unwrap();
+        for name in ["whered.toml", "sessions.json"] {
+            let new = dir.join(format!("{name}.new"));
+            std::fs::write(&new, "new").unwrap();
+            std::fs::rename(&new, dir.join(name)).unwrap();
+        }
+        replaced_tx.send(()).unwrap();
+
+        let (config, sessions) = reader.join().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(config.unwrap(), "new");
+        assert_eq!(sessions.unwrap(), "new");
+    }
+}
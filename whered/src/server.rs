@@ -0,0 +1,323 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use whrd::error::{EncodeDecodeResult, Report, WhereResult};
+use whrd::{HistoryQuery, HostInfo, LoginHistory, Request, SessionCollection};
+use crate::cache::SnapshotCache;
+use crate::config::Config;
+use crate::metrics::{Metrics, SessionCounts};
+use crate::source::{build_source, BoxedSource};
+
+// A payload shared by the requests answered from the cache, or one encoded for
+// a single request
+pub enum Payload<'a> {
+    Cached(Arc<[u8]>),
+    Encoded(&'a [u8])
+}
+
+impl Deref for Payload<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Cached(payload) => payload,
+            Self::Encoded(payload) => payload
+        }
+    }
+}
+
+// Everything needed to answer requests, shared by all the workers
+pub struct State {
+    pub config: Config,
+    pub metrics: Arc<Metrics>,
+    source: BoxedSource,
+    cache: Mutex<SnapshotCache<[u8]>>,
+    // Every login in the wtmp file, once filter and privacy settings are applied
+    history: Option<Mutex<SnapshotCache<LoginHistory>>>
+}
+
+impl State {
+    pub fn new(config: Config, metrics: Arc<Metrics>) -> Self {
+        let source = build_source(&config.source, &config.containers, &metrics);
+        let ttl = Duration::from_millis(config.cache.ttl);
+        let watch_paths = if config.cache.watch {
+            source.watch_paths()
+        } else {
+            vec![]
+        };
+        let cache = SnapshotCache::new(ttl, watch_paths);
+
+        // The wtmp file only grows, and reading all of it for every request would let
+        // anyone make the server do a lot of work with a single datagram
+        let history = config.history.enabled.then(|| {
+            let watch_paths = if config.cache.watch {
+                vec![config.history.path.clone()]
+            } else {
+                vec![]
+            };
+
+            Mutex::new(SnapshotCache::new(ttl, watch_paths))
+        });
+
+        Self {
+            config,
+            metrics,
+            source,
+            cache: Mutex::new(cache),
+            history
+        }
+    }
+
+    // Returns None for requests that should be left unanswered. Payloads are
+    // encoded into the buffer, which is reused between requests.
+    pub fn handle_request<'a>(&self, request: &[u8], src: &SocketAddr, buffer: &'a mut Vec<u8>) -> WhereResult<Option<Payload<'a>>> {
+        buffer.clear();
+
+        // Anyone can send datagrams, so these are not worth more than a debug message
+        let request = match Request::from_udp_payload(request) {
+            Ok(request) => request,
+            Err(e) => {
+                log::debug!(client:% = src; "Ignoring malformed request: {}", Report(&e));
+                return Ok(None);
+            }
+        };
+
+        match request {
+            Request::Sessions => {
+                // Concurrent requests wait for a single refresh instead of all doing their own
+                let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+                let payload = cache.get(|| {
+                    self.get_sessions(buffer)?;
+                    Ok(Arc::from(buffer.as_slice()))
+                })?;
+
+                Ok(Some(Payload::Cached(payload)))
+            }
+            Request::History(query) => {
+                let Some(cache) = &self.history else {
+                    log::debug!(client:% = src; "Ignoring history request, history is disabled");
+                    return Ok(None);
+                };
+
+                let history = cache.lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .get(|| self.get_history().map(Arc::new))?;
+                let matching = history.iter()
+                    .filter(|entry| query.matches(entry))
+                    .cloned()
+                    .collect();
+
+                self.count_encode_errors(LoginHistory::from_vec(matching).write_udp_payload(buffer))?;
+                Ok(Some(Payload::Encoded(buffer)))
+            }
+        }
+    }
+
+    fn get_sessions(&self, buffer: &mut Vec<u8>) -> WhereResult<()> {
+        let config = &self.config;
+        let started_at = Instant::now();
+        let mut sessions = SessionCollection::fetch_from(self.source.as_ref(), |s| config.filter.allows(s))?
+            .with_host_info(HostInfo::system().ok());
+
+        if let (Some(hostname), Some(host_info)) = (&config.global.hostname, sessions.host_info_mut()) {
+            host_info.hostname = hostname.clone();
+        }
+
+        let mut counts = SessionCounts {
+            active: 0,
+            inactive: 0,
+            users: BTreeMap::new()
+        };
+
+        for session in sessions.iter_mut() {
+            config.privacy.apply(session);
+
+            if session.active {
+                counts.active += 1;
+            } else {
+                counts.inactive += 1;
+            }
+
+            if config.metrics.per_user {
+                *counts.users.entry(session.user.clone()).or_default() += 1;
+            }
+        }
+
+        let length = self.count_encode_errors(sessions.write_udp_payload(buffer))?;
+        self.metrics.record_fetch(started_at.elapsed(), counts);
+        log::debug!(bytes = length; "Refreshed the list of sessions");
+
+        Ok(())
+    }
+
+    fn get_history(&self) -> WhereResult<LoginHistory> {
+        let config = &self.config;
+        let everything = HistoryQuery {
+            since: i64::MIN,
+            until: i64::MAX,
+            user: None
+        };
+
+        let mut history = LoginHistory::from_wtmp_file(&config.history.path, &everything)?;
+        history.retain(|entry| config.filter.allows(&entry.session));
+
+        // Clients only know users by the names they were sent, which may be hashed,
+        // so queries are matched once privacy settings have been applied
+        for entry in history.iter_mut() {
+            config.privacy.apply(&mut entry.session);
+        }
+
+        log::debug!(entries = history.len(); "Read the login history");
+        Ok(history)
+    }
+
+    fn count_encode_errors(&self, result: EncodeDecodeResult<usize>) -> EncodeDecodeResult<usize> {
+        if result.is_err() {
+            self.metrics.record_encode_error();
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    use std::path::PathBuf;
+    use crate::privacy::UserPolicy;
+    use super::*;
+
+    fn record(kind: libc::c_short, user: &str, tty: &str, pid: i32, time: i64) -> libc::utmpx {
+        let mut record: libc::utmpx = unsafe { std::mem::zeroed() };
+        record.ut_type = kind;
+        record.ut_pid = pid;
+        for (dest, byte) in record.ut_user.iter_mut().zip(user.bytes()) {
+            *dest = byte as libc::c_char;
+        }
+        for (dest, byte) in record.ut_line.iter_mut().zip(tty.bytes()) {
+            *dest = byte as libc::c_char;
+        }
+        record.ut_tv.tv_sec = time as _;
+        record
+    }
+
+    fn write_wtmp(path: &PathBuf) {
+        write_records(path, &[
+            record(libc::USER_PROCESS, "alice", "pts/0", 42, 1_700_000_100),
+            record(libc::USER_PROCESS, "bob", "pts/1", 43, 1_700_000_200),
+            record(libc::DEAD_PROCESS, "", "pts/0", 42, 1_700_000_300)
+        ]);
+    }
+
+    fn write_records(path: &PathBuf, records: &[libc::utmpx]) {
+        let bytes: Vec<u8> = records.iter()
+            .flat_map(|record| unsafe {
+                std::slice::from_raw_parts(record as *const libc::utmpx as *const u8, size_of::<libc::utmpx>())
+            })
+            .copied()
+            .collect();
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    fn history_request(state: &State, user: Option<&str>) -> Option<Vec<u8>> {
+        let request = Request::History(HistoryQuery {
+            since: 0,
+            until: i64::MAX,
+            user: user.map(str::to_string)
+        });
+        let src = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1234));
+        let mut buffer = vec![];
+
+        let payload = state.handle_request(&request.to_udp_payload().unwrap(), &src, &mut buffer).unwrap();
+        payload.map(|payload| payload.to_vec())
+    }
+
+    fn history_users(state: &State, user: Option<&str>) -> Vec<String> {
+        LoginHistory::from_udp_payload(&history_request(state, user).unwrap(), "localhost")
+            .unwrap()
+            .into_vec()
+            .into_iter()
+            .map(|entry| entry.session.user)
+            .collect()
+    }
+
+    #[test]
+    fn history_user_filter_matches_hashed_names() {
+        let path = std::env::temp_dir().join(format!("whered-server-test-{}-wtmp", std::process::id()));
+        write_wtmp(&path);
+
+        let mut config = Config::default();
+        config.history.enabled = true;
+        config.history.path = path.clone();
+        config.privacy.users = UserPolicy::Hashed;
+        config.privacy.salt = Some("salt".to_string());
+        let state = State::new(config, Arc::default());
+
+        let users = history_users(&state, None);
+        assert_eq!(users.len(), 2);
+        assert!(!users.contains(&"alice".to_string()));
+
+        // Real names would let clients guess which hash belongs to whom
+        assert!(history_users(&state, Some("alice")).is_empty());
+        assert_eq!(history_users(&state, Some(&users[0])), [users[0].clone()]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn malformed_requests_are_ignored() {
+        let state = State::new(Config::default(), Arc::default());
+        let src = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1234));
+        let mut buffer = vec![];
+
+        assert!(state.handle_request(b"garbage", &src, &mut buffer).unwrap().is_none());
+    }
+
+    #[test]
+    fn history_is_disabled_by_default() {
+        let state = State::new(Config::default(), Arc::default());
+        assert_eq!(history_request(&state, None), None);
+    }
+
+    #[test]
+    fn history_is_cached_until_wtmp_changes() {
+        let path = std::env::temp_dir().join(format!("whered-server-test-{}-cached-wtmp", std::process::id()));
+        write_wtmp(&path);
+
+        let mut config = Config::default();
+        config.history.enabled = true;
+        config.history.path = path.clone();
+        config.cache.ttl = u64::MAX;
+        config.cache.watch = false;
+        let state = State::new(config, Arc::default());
+
+        assert_eq!(history_users(&state, None), ["alice", "bob"]);
+
+        write_records(&path, &[record(libc::USER_PROCESS, "carol", "pts/2", 44, 1_700_000_400)]);
+        assert_eq!(history_users(&state, None), ["alice", "bob"]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn history_is_read_again_when_wtmp_changes() {
+        let path = std::env::temp_dir().join(format!("whered-server-test-{}-watched-wtmp", std::process::id()));
+        write_wtmp(&path);
+
+        let mut config = Config::default();
+        config.history.enabled = true;
+        config.history.path = path.clone();
+        config.cache.ttl = u64::MAX;
+        let state = State::new(config, Arc::default());
+
+        assert_eq!(history_users(&state, None), ["alice", "bob"]);
+
+        write_records(&path, &[record(libc::USER_PROCESS, "carol", "pts/2", 44, 1_700_000_400)]);
+        assert_eq!(history_users(&state, None), ["carol"]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,52 @@
+use std::io;
+use std::mem::MaybeUninit;
+
+pub enum Signal {
+    Reload,
+    Terminate
+}
+
+// Signals are blocked in every thread and waited for by a single one, which
+// avoids doing anything in a signal handler
+pub struct Signals {
+    set: libc::sigset_t
+}
+
+impl Signals {
+    // Must be called before any thread is started, as they inherit the signal mask
+    pub fn block() -> io::Result<Self> {
+        let mut set = MaybeUninit::<libc::sigset_t>::uninit();
+
+        let set = unsafe {
+            libc::sigemptyset(set.as_mut_ptr());
+            libc::sigaddset(set.as_mut_ptr(), libc::SIGHUP);
+            libc::sigaddset(set.as_mut_ptr(), libc::SIGINT);
+            libc::sigaddset(set.as_mut_ptr(), libc::SIGTERM);
+            set.assume_init()
+        };
+
+        let res = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) };
+        if res != 0 {
+            return Err(io::Error::from_raw_os_error(res));
+        }
+
+        Ok(Self {
+            set
+        })
+    }
+
+    pub fn wait(&self) -> io::Result<Signal> {
+        let mut signal = 0;
+
+        let res = unsafe { libc::sigwait(&self.set, &mut signal) };
+        if res != 0 {
+            return Err(io::Error::from_raw_os_error(res));
+        }
+
+        if signal == libc::SIGHUP {
+            Ok(Signal::Reload)
+        } else {
+            Ok(Signal::Terminate)
+        }
+    }
+}
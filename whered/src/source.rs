@@ -0,0 +1,252 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use serde::Deserialize;
+use whrd::error::{Report, WhereResult};
+use whrd::{Session, SessionSource, UtmpFileSource, UtmpxSource, MAX_CONTAINER_LENGTH};
+use crate::metrics::Metrics;
+use crate::relay::{RelayConfig, RelaySource};
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SourceConfig {
+    #[default]
+    Utmpx,
+    Utmp {
+        path: PathBuf
+    },
+    Json {
+        path: PathBuf
+    },
+    Command {
+        command: Vec<String>
+    },
+    Logind {
+        #[serde(default)]
+        #[cfg_attr(not(all(target_os = "linux", feature = "logind")), allow(dead_code))]
+        address: Option<String>
+    },
+    Relay(RelayConfig)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ContainerConfig {
+    pub name: String,
+    pub root: PathBuf,
+    #[serde(default)]
+    pub utmp: Option<PathBuf>
+}
+
+pub type BoxedSource = Box<dyn SessionSource + Send + Sync>;
+
+pub fn build_source(source: &SourceConfig, containers: &[ContainerConfig], metrics: &Arc<Metrics>) -> BoxedSource {
+    if containers.is_empty() {
+        return source.build(metrics);
+    }
+
+    Box::new(CombinedSource {
+        host: source.build(metrics),
+        containers: containers.iter().map(ContainerConfig::build).collect()
+    })
+}
+
+impl SourceConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::Command { command } if command.is_empty() => Err("source.command must not be empty".to_string()),
+            #[cfg(not(all(target_os = "linux", feature = "logind")))]
+            Self::Logind { .. } => Err("whered was built without systemd-logind support".to_string()),
+            Self::Relay(relay) => relay.validate(),
+            _ => Ok(())
+        }
+    }
+
+    // The files this source reads, or None if it can read anything (such as commands)
+    pub fn read_paths(&self) -> Option<Vec<PathBuf>> {
+        match self {
+            Self::Utmpx => Some(UtmpxSource.watch_paths()),
+            Self::Utmp { path } | Self::Json { path } => Some(vec![path.clone()]),
+            Self::Command { .. } => None,
+            // D-Bus and upstream servers are reached through sockets, which don't need any file access
+            Self::Logind { .. } | Self::Relay(_) => Some(vec![])
+        }
+    }
+
+    pub fn build(&self, metrics: &Arc<Metrics>) -> BoxedSource {
+        match self {
+            Self::Utmpx => Box::new(UtmpxSource),
+            Self::Utmp { path } => Box::new(UtmpFileSource::new(path)),
+            Self::Json { path } => Box::new(JsonFileSource { path: path.clone() }),
+            Self::Command { command } => Box::new(CommandSource { command: command.clone() }),
+            Self::Relay(relay) => Box::new(RelaySource::new(relay.clone(), Arc::clone(metrics))),
+            #[cfg(all(target_os = "linux", feature = "logind"))]
+            Self::Logind { address } => Box::new(crate::logind::LogindSource::new(address.clone())),
+            // Rejected when validating the configuration
+            #[cfg(not(all(target_os = "linux", feature = "logind")))]
+            Self::Logind { .. } => unreachable!()
+        }
+    }
+}
+
+impl ContainerConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() || self.name.len() > MAX_CONTAINER_LENGTH {
+            return Err(format!("Container names must be between 1 and {MAX_CONTAINER_LENGTH} bytes long: {:?}", self.name));
+        }
+
+        Ok(())
+    }
+
+    pub fn read_paths(&self) -> Vec<PathBuf> {
+        let utmp = self.utmp.clone().unwrap_or_else(|| find_utmp(&self.root));
+        vec![utmp, self.root.join("dev")]
+    }
+
+    fn build(&self) -> ContainerSource {
+        let utmp = self.utmp.clone().unwrap_or_else(|| find_utmp(&self.root));
+
+        ContainerSource {
+            name: self.name.clone(),
+            source: UtmpFileSource::new(utmp).with_dev_dir(self.root.join("dev"))
+        }
+    }
+}
+
+// /var/run is usually an absolute symlink to /run, which would be resolved
+// outside of the container's root, so /run is tried first
+fn find_utmp(root: &Path) -> PathBuf {
+    let run = root.join("run/utmp");
+
+    if run.exists() {
+        run
+    } else {
+        root.join("var/run/utmp")
+    }
+}
+
+struct ContainerSource {
+    name: String,
+    source: UtmpFileSource
+}
+
+impl SessionSource for ContainerSource {
+    fn sessions(&self) -> WhereResult<Vec<Session>> {
+        let mut sessions = self.source.sessions()?;
+
+        for session in &mut sessions {
+            session.container = Some(self.name.clone());
+        }
+
+        Ok(sessions)
+    }
+
+    fn watch_paths(&self) -> Vec<PathBuf> {
+        self.source.watch_paths()
+    }
+}
+
+struct CombinedSource {
+    host: BoxedSource,
+    containers: Vec<ContainerSource>
+}
+
+impl SessionSource for CombinedSource {
+    fn sessions(&self) -> WhereResult<Vec<Session>> {
+        let mut sessions = self.host.sessions()?;
+
+        // A stopped container should not prevent the others from being reported
+        for container in &self.containers {
+            match container.sessions() {
+                Ok(res) => sessions.extend(res),
+                Err(e) => log::warn!(container = container.name.as_str(); "Unable to read sessions from container: {}", Report(&e))
+            }
+        }
+
+        Ok(sessions)
+    }
+
+    fn watch_paths(&self) -> Vec<PathBuf> {
+        let mut paths = self.host.watch_paths();
+        paths.extend(self.containers.iter().flat_map(|c| c.watch_paths()));
+        paths
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonSession {
+    user: String,
+    tty: String,
+    #[serde(default)]
+    pid: i32,
+    login_time: i64,
+    #[serde(default)]
+    remote: Option<String>,
+    #[serde(default = "default_active")]
+    active: bool,
+    #[serde(default)]
+    container: Option<String>
+}
+
+fn default_active() -> bool {
+    true
+}
+
+impl From<JsonSession> for Session {
+    fn from(session: JsonSession) -> Self {
+        let mut session = Self {
+            host: None,
+            pid: session.pid,
+            login_time: session.login_time,
+            user: session.user,
+            tty: session.tty,
+            remote: session.remote,
+            active: session.active,
+            container: session.container
+        };
+
+        // Unlike utmp, JSON doesn't limit how long fields can be
+        session.truncate_to_limits();
+        session
+    }
+}
+
+fn parse_json(data: &[u8]) -> WhereResult<Vec<Session>> {
+    let sessions: Vec<JsonSession> = serde_json::from_slice(data)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    Ok(sessions.into_iter().map(Session::from).collect())
+}
+
+pub struct JsonFileSource {
+    path: PathBuf
+}
+
+impl SessionSource for JsonFileSource {
+    fn sessions(&self) -> WhereResult<Vec<Session>> {
+        parse_json(&fs::read(&self.path)?)
+    }
+
+    fn watch_paths(&self) -> Vec<PathBuf> {
+        vec![self.path.clone()]
+    }
+}
+
+pub struct CommandSource {
+    command: Vec<String>
+}
+
+impl SessionSource for CommandSource {
+    fn sessions(&self) -> WhereResult<Vec<Session>> {
+        let output = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .output()?;
+
+        if !output.status.success() {
+            return Err(io::Error::other(format!("{} exited with {}", self.command[0], output.status)))?;
+        }
+
+        parse_json(&output.stdout)
+    }
+}
@@ -0,0 +1,244 @@
+use std::env;
+use std::io;
+use std::net::UdpSocket;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// The first file descriptor passed by systemd, see sd_listen_fds(3)
+const LISTEN_FDS_START: RawFd = 3;
+
+// Returns the UDP sockets passed by systemd through socket activation
+pub fn listen_fds() -> Vec<UdpSocket> {
+    let pid_matches = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<RawFd>().ok())
+        .unwrap_or_default();
+
+    // These must not be inherited by child processes, such as command sources
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    if !pid_matches {
+        return vec![];
+    }
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .filter(|fd| {
+            let is_datagram = is_datagram_socket(*fd);
+            if !is_datagram {
+                log::warn!("Ignoring file descriptor {fd} passed by systemd, it is not a datagram socket");
+            }
+            is_datagram
+        })
+        .map(|fd| {
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+            unsafe { UdpSocket::from_raw_fd(fd) }
+        })
+        .collect()
+}
+
+fn is_datagram_socket(fd: RawFd) -> bool {
+    let mut socket_type: libc::c_int = 0;
+    let mut length = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+    let res = unsafe {
+        libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_TYPE, &mut socket_type as *mut libc::c_int as *mut libc::c_void, &mut length)
+    };
+
+    res == 0 && socket_type == libc::SOCK_DGRAM
+}
+
+// Connected once, as the socket may not be reachable anymore after changing
+// the root directory
+static NOTIFY_SOCKET: OnceLock<Option<UnixDatagram>> = OnceLock::new();
+
+// Connects to the socket systemd expects notifications on. Must be called
+// before changing the root directory.
+pub fn connect_notify_socket() {
+    NOTIFY_SOCKET.get_or_init(|| {
+        let path = env::var_os("NOTIFY_SOCKET")?;
+
+        // This must not be inherited by child processes, such as command sources
+        env::remove_var("NOTIFY_SOCKET");
+
+        match connect_to(&path.to_string_lossy()) {
+            Ok(socket) => Some(socket),
+            Err(e) => {
+                log::warn!("Unable to connect to systemd: {e}");
+                None
+            }
+        }
+    });
+}
+
+// Paths starting with @ are abstract socket names
+fn connect_to(path: &str) -> io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+
+    match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.connect_addr(&addr)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => return Err(io::Error::new(io::ErrorKind::Unsupported, "Abstract sockets are only supported on Linux")),
+        None => socket.connect(path)?
+    }
+
+    Ok(socket)
+}
+
+// Sends a state change to systemd, see sd_notify(3). Does nothing when not
+// started by systemd with Type=notify.
+pub fn notify(state: &str) {
+    connect_notify_socket();
+
+    let Some(Some(socket)) = NOTIFY_SOCKET.get() else {
+        return;
+    };
+
+    if let Err(e) = socket.send(state.as_bytes()) {
+        log::warn!("Unable to notify systemd: {e}");
+    }
+}
+
+struct Watchdog {
+    interval: Duration,
+    last_ping: Mutex<Option<Instant>>
+}
+
+impl Watchdog {
+    // Reads the variables systemd sets when the watchdog is enabled, which are
+    // meant for the given process
+    fn from_env_values(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Self> {
+        let pid_matches = pid
+            .and_then(|pid| pid.parse::<u32>().ok())
+            .is_none_or(|pid| pid == own_pid);
+
+        let interval = usec
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|usec| *usec > 0 && pid_matches)
+            .map(Duration::from_micros)?;
+
+        Some(Self {
+            interval,
+            last_ping: Mutex::new(None)
+        })
+    }
+
+    // At most twice per interval
+    fn should_ping(&self) -> bool {
+        let mut last_ping = self.last_ping.lock().unwrap_or_else(|e| e.into_inner());
+        if last_ping.is_some_and(|time| time.elapsed() < self.interval / 2) {
+            return false;
+        }
+
+        *last_ping = Some(Instant::now());
+        true
+    }
+}
+
+static WATCHDOG: OnceLock<Option<Watchdog>> = OnceLock::new();
+
+// Reads the interval systemd expects watchdog pings at, if it is enabled
+pub fn enable_watchdog() {
+    WATCHDOG.get_or_init(|| {
+        let usec = env::var("WATCHDOG_USEC").ok();
+        let pid = env::var("WATCHDOG_PID").ok();
+
+        // These must not be inherited by child processes, such as command sources
+        env::remove_var("WATCHDOG_PID");
+        env::remove_var("WATCHDOG_USEC");
+
+        Watchdog::from_env_values(usec.as_deref(), pid.as_deref(), std::process::id())
+    });
+}
+
+pub fn watchdog_interval() -> Option<Duration> {
+    WATCHDOG.get()?.as_ref().map(|watchdog| watchdog.interval)
+}
+
+// Tells systemd the server still works, at most twice per interval it expects.
+// Callers are the ones who know whether it does.
+pub fn ping_watchdog() {
+    let Some(Some(watchdog)) = WATCHDOG.get() else {
+        return;
+    };
+
+    if watchdog.should_ping() {
+        notify("WATCHDOG=1");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchdog_interval_from_env_values() {
+        let interval = |usec, pid| Watchdog::from_env_values(usec, pid, 42).map(|watchdog| watchdog.interval);
+
+        assert_eq!(interval(Some("200000"), Some("42")), Some(Duration::from_millis(200)));
+        // WATCHDOG_PID is optional
+        assert_eq!(interval(Some("200000"), None), Some(Duration::from_millis(200)));
+        // Meant for another process, such as the one that started this one
+        assert_eq!(interval(Some("200000"), Some("41")), None);
+        assert_eq!(interval(Some("0"), None), None);
+        assert_eq!(interval(Some("soon"), None), None);
+        assert_eq!(interval(None, Some("42")), None);
+    }
+
+    #[test]
+    fn watchdog_pings_are_rate_limited() {
+        let watchdog = Watchdog::from_env_values(Some("200000"), None, 42).unwrap();
+
+        assert!(watchdog.should_ping());
+        assert!(!watchdog.should_ping());
+
+        std::thread::sleep(Duration::from_millis(120));
+        assert!(watchdog.should_ping());
+        assert!(!watchdog.should_ping());
+    }
+
+    #[test]
+    fn notify_socket() {
+        let path = env::temp_dir().join(format!("whered-notify-test-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let systemd = UnixDatagram::bind(&path).unwrap();
+
+        let socket = connect_to(path.to_str().unwrap()).unwrap();
+        socket.send(b"READY=1").unwrap();
+
+        let mut buf = [0; 64];
+        let length = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..length], b"READY=1");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn abstract_notify_socket() {
+        use std::os::linux::net::SocketAddrExt;
+
+        let name = format!("whered-notify-test-{}", std::process::id());
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(&name).unwrap();
+        let systemd = UnixDatagram::bind_addr(&addr).unwrap();
+
+        connect_to(&format!("@{name}")).unwrap().send(b"WATCHDOG=1").unwrap();
+
+        let mut buf = [0; 64];
+        let length = systemd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..length], b"WATCHDOG=1");
+    }
+}
@@ -0,0 +1,78 @@
+use std::ffi::{CStr, CString};
+use std::mem::MaybeUninit;
+use std::ptr;
+
+const BUFFER_SIZE: usize = 16384;
+const MAX_GROUPS: usize = 256;
+
+#[derive(Debug)]
+pub struct Account {
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+    pub groups: Vec<libc::gid_t>
+}
+
+impl Account {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let c_name = CString::new(name).ok()?;
+        let mut passwd = MaybeUninit::<libc::passwd>::uninit();
+        let mut result = ptr::null_mut();
+        let mut buffer = vec![0 as libc::c_char; BUFFER_SIZE];
+
+        let res = unsafe {
+            libc::getpwnam_r(c_name.as_ptr(), passwd.as_mut_ptr(), buffer.as_mut_ptr(), buffer.len(), &mut result)
+        };
+
+        if res != 0 || result.is_null() {
+            return None;
+        }
+
+        let passwd = unsafe { passwd.assume_init() };
+        let groups = get_group_list(&c_name, passwd.pw_gid);
+
+        Some(Self {
+            uid: passwd.pw_uid,
+            gid: passwd.pw_gid,
+            groups
+        })
+    }
+}
+
+pub fn group_id(name: &str) -> Option<libc::gid_t> {
+    let c_name = CString::new(name).ok()?;
+    let mut group = MaybeUninit::<libc::group>::uninit();
+    let mut result = ptr::null_mut();
+    let mut buffer = vec![0 as libc::c_char; BUFFER_SIZE];
+
+    let res = unsafe {
+        libc::getgrnam_r(c_name.as_ptr(), group.as_mut_ptr(), buffer.as_mut_ptr(), buffer.len(), &mut result)
+    };
+
+    if res != 0 || result.is_null() {
+        None
+    } else {
+        Some(unsafe { group.assume_init() }.gr_gid)
+    }
+}
+
+#[cfg(not(target_vendor = "apple"))]
+fn get_group_list(name: &CStr, gid: libc::gid_t) -> Vec<libc::gid_t> {
+    let mut groups = vec![0 as libc::gid_t; MAX_GROUPS];
+    let mut count = groups.len() as libc::c_int;
+
+    unsafe { libc::getgrouplist(name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+
+    groups.truncate(count.clamp(0, MAX_GROUPS as libc::c_int) as usize);
+    groups
+}
+
+#[cfg(target_vendor = "apple")]
+fn get_group_list(name: &CStr, gid: libc::gid_t) -> Vec<libc::gid_t> {
+    let mut groups = vec![0 as libc::c_int; MAX_GROUPS];
+    let mut count = groups.len() as libc::c_int;
+
+    unsafe { libc::getgrouplist(name.as_ptr(), gid as libc::c_int, groups.as_mut_ptr(), &mut count) };
+
+    groups.truncate(count.clamp(0, MAX_GROUPS as libc::c_int) as usize);
+    groups.into_iter().map(|g| g as libc::gid_t).collect()
+}
@@ -0,0 +1,169 @@
+use std::future::Future;
+use std::io::{self, ErrorKind, Write};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::{lookup_host, UdpSocket};
+
+use crate::client::{Client, Response, Server};
+use crate::error::{EncodeDecodeResult, WhereError, WhereResult};
+use crate::{HistoryQuery, LoginHistory, Request, SessionCollection, MAX_PAYLOAD_LENGTH, MAX_REQUEST_LENGTH};
+
+type Decoder<T> = fn(&[u8], &str) -> WhereResult<T>;
+
+/// What a server sends back to a request
+#[derive(Debug)]
+pub enum Reply {
+    Sessions(SessionCollection),
+    History(LoginHistory)
+}
+
+impl Reply {
+    /// Encodes the reply, returning how many bytes were written
+    pub fn write_udp_payload(&self, writer: &mut impl Write) -> EncodeDecodeResult<usize> {
+        match self {
+            Self::Sessions(collection) => collection.write_udp_payload(writer),
+            Self::History(history) => history.write_udp_payload(writer)
+        }
+    }
+}
+
+impl Client {
+    /// Same as [`Client::sessions`], without blocking the runtime
+    pub async fn sessions_async(&self) -> Vec<Response<SessionCollection>> {
+        self.run_async(Request::Sessions, SessionCollection::from_udp_payload).await
+    }
+
+    /// Same as [`Client::history`], without blocking the runtime
+    pub async fn history_async(&self, query: HistoryQuery) -> Vec<Response<LoginHistory>> {
+        self.run_async(Request::History(query), LoginHistory::from_udp_payload).await
+    }
+
+    async fn run_async<T: Send + 'static>(&self, request: Request, decode: Decoder<T>) -> Vec<Response<T>> {
+        // Every server gets its own task, so that they are queried at the same time
+        let tasks: Vec<_> = self.servers.iter()
+            .map(|server| {
+                let (timeout, retries) = self.settings(server);
+                let (server, port, request) = (server.clone(), self.port, request.clone());
+
+                tokio::spawn(async move {
+                    query_server(&server, port, timeout, retries, &request, decode).await
+                })
+            })
+            .collect();
+
+        let mut responses = vec![];
+
+        for task in tasks {
+            match task.await {
+                Ok(response) => responses.push(response),
+                Err(e) => std::panic::resume_unwind(e.into_panic())
+            }
+        }
+
+        responses
+    }
+}
+
+async fn query_server<T>(server: &Server, port: u16, timeout: Duration, retries: usize, request: &Request, decode: Decoder<T>) -> Response<T> {
+    let started_at = Instant::now();
+    let label = server.label();
+
+    let mut attempts = 0;
+    let result = query(server.endpoint(), port, timeout, retries, request, |buf| decode(buf, label), &mut attempts).await;
+
+    Response::new(server, started_at, attempts, result)
+}
+
+/// Same as [`crate::client::get_address`], without blocking the runtime
+pub async fn get_address(endpoint: &str, default_port: u16) -> WhereResult<SocketAddr> {
+    let addresses: Vec<_> = match lookup_host(endpoint).await {
+        Ok(addresses) => addresses.collect(),
+        Err(_) => lookup_host(format!("{endpoint}:{default_port}")).await?.collect()
+    };
+
+    addresses.into_iter()
+        .find(|i| i.is_ipv4())
+        .ok_or_else(|| WhereError::from(io::Error::new(ErrorKind::AddrNotAvailable, format!("No IPv4 address found for {endpoint}"))))
+}
+
+// Sends a request to a server, trying again up to retries times if it doesn't
+// answer within the timeout
+async fn query<T, F>(endpoint: &str, default_port: u16, timeout: Duration, retries: usize, request: &Request, decode: F, attempts: &mut usize) -> WhereResult<(T, Duration)>
+where
+    F: Fn(&[u8]) -> WhereResult<T>
+{
+    let request = request.to_udp_payload()?;
+    let address = get_address(endpoint, default_port).await?;
+    let socket = UdpSocket::bind(if address.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    }).await?;
+
+    let mut buf = vec![0; MAX_PAYLOAD_LENGTH];
+    let mut decode_error = None;
+
+    for attempt in 1..=retries {
+        *attempts = attempt;
+
+        let sent_at = Instant::now();
+        socket.send_to(&request, address).await?;
+
+        let deadline = tokio::time::Instant::from_std(sent_at + timeout);
+
+        // Anyone can send datagrams to the socket, only the server can answer
+        while let Ok(res) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+            let (length, from) = res?;
+            if from != address {
+                continue;
+            }
+
+            let rtt = sent_at.elapsed();
+
+            // Replies that can't be decoded may have been corrupted on the way, so
+            // they are given another attempt like lost ones
+            match decode(&buf[..length]) {
+                Ok(res) => return Ok((res, rtt)),
+                Err(e @ WhereError::EncodeDecodeError(_)) => {
+                    decode_error = Some(e);
+                    break;
+                }
+                Err(e) => return Err(e)
+            }
+        }
+    }
+
+    Err(decode_error.unwrap_or_else(|| WhereError::TimedOut(endpoint.to_string(), address.to_string(), retries, timeout)))
+}
+
+/// Answers the requests received on a socket with what the handler returns for
+/// them, one request at a time, until the socket fails. Invalid requests and
+/// ones the handler returns None for are left unanswered.
+pub async fn serve<F, Fut>(socket: UdpSocket, handler: F) -> io::Result<()>
+where
+    F: Fn(Request, SocketAddr) -> Fut,
+    Fut: Future<Output = Option<Reply>>
+{
+    let mut buf = [0; MAX_REQUEST_LENGTH];
+    let mut payload = Vec::with_capacity(MAX_PAYLOAD_LENGTH);
+
+    loop {
+        let (length, src) = socket.recv_from(&mut buf).await?;
+
+        let Ok(request) = Request::from_udp_payload(&buf[..length]) else {
+            continue;
+        };
+
+        let Some(reply) = handler(request, src).await else {
+            continue;
+        };
+
+        payload.clear();
+        if reply.write_udp_payload(&mut payload).is_err() {
+            continue;
+        }
+
+        // A client that went away must not stop the server
+        let _ = socket.send_to(&payload, src).await;
+    }
+}
@@ -0,0 +1,309 @@
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{WhereError, WhereResult};
+use crate::{HistoryQuery, LoginHistory, Request, SessionCollection, MAX_PAYLOAD_LENGTH};
+
+/// The port used for endpoints that do not include one, unless set for the client
+pub const DEFAULT_PORT: u16 = 15;
+/// How long to wait for each attempt, unless set for the client or the server
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(2000);
+/// How many requests to send before giving up, unless set for the client or the server
+pub const DEFAULT_MAX_RETRIES: usize = 3;
+
+/// A server to query, with optional settings overriding the ones of the client
+#[derive(Debug, Clone)]
+pub struct Server {
+    pub(crate) endpoint: String,
+    pub(crate) label: Option<String>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) max_retries: Option<usize>
+}
+
+impl Server {
+    /// The endpoint is a host name or address, with an optional port
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            label: None,
+            timeout: None,
+            max_retries: None
+        }
+    }
+
+    /// The name sessions are reported with, instead of the endpoint
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// How long to wait for each attempt, instead of the timeout of the client
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// How many requests to send before giving up, instead of the number set
+    /// for the client
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// The label of the server, or its endpoint if it has none
+    pub fn label(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.endpoint)
+    }
+}
+
+/// Queries a set of servers at once
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use whrd::client::{Client, Server};
+/// use whrd::error::Report;
+///
+/// let client = Client::new()
+///     .with_timeout(Duration::from_millis(500))
+///     .with_server(Server::new("10.0.0.1").with_label("web"))
+///     .with_server(Server::new("db.example.org:1515"));
+///
+/// for response in client.sessions() {
+///     match response.result {
+///         Ok(sessions) => println!("{}: {} sessions", response.label, sessions.len()),
+///         Err(e) => eprintln!("{}: {}", response.label, Report(&e))
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub(crate) servers: Vec<Server>,
+    pub(crate) port: u16,
+    pub(crate) timeout: Duration,
+    pub(crate) max_retries: usize
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self {
+            servers: vec![],
+            port: DEFAULT_PORT,
+            timeout: DEFAULT_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES
+        }
+    }
+}
+
+/// The answer of one server, along with how long it took
+#[derive(Debug)]
+pub struct Response<T> {
+    /// The label of the server, or its endpoint if it has none
+    pub label: String,
+    pub endpoint: String,
+    pub result: WhereResult<T>,
+    /// How many requests were sent, including the one that was answered
+    pub attempts: usize,
+    /// Time between the answered request and its answer
+    pub rtt: Option<Duration>,
+    /// Time spent on the server, including resolving its address and retries
+    pub elapsed: Duration
+}
+
+impl<T> Response<T> {
+    pub(crate) fn new(server: &Server, started_at: Instant, attempts: usize, result: WhereResult<(T, Duration)>) -> Self {
+        let (result, rtt) = match result {
+            Ok((value, rtt)) => (Ok(value), Some(rtt)),
+            Err(e) => (Err(e), None)
+        };
+
+        Self {
+            label: server.label().to_string(),
+            endpoint: server.endpoint.clone(),
+            result,
+            attempts,
+            rtt,
+            elapsed: started_at.elapsed()
+        }
+    }
+
+    /// Whether the server answered with a valid payload
+    pub fn is_ok(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+impl Client {
+    /// A client without any server, using the default settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The port used for endpoints that do not include one
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// How long to wait for each attempt, unless set for the server
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How many requests to send before giving up, unless set for the server
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Adds a server to query, after the ones already added
+    pub fn with_server(mut self, server: Server) -> Self {
+        self.servers.push(server);
+        self
+    }
+
+    /// Adds servers to query, after the ones already added
+    pub fn with_servers(mut self, servers: impl IntoIterator<Item = Server>) -> Self {
+        self.servers.extend(servers);
+        self
+    }
+
+    pub fn servers(&self) -> &[Server] {
+        &self.servers
+    }
+
+    /// Lists the sessions of every server, in the order they were added. Sessions
+    /// have the label of their server as their host, unless they were relayed
+    /// from another one.
+    pub fn sessions(&self) -> Vec<Response<SessionCollection>> {
+        self.run(&Request::Sessions, SessionCollection::from_udp_payload)
+    }
+
+    /// Lists the past logins of every server, in the order they were added
+    pub fn history(&self, query: HistoryQuery) -> Vec<Response<LoginHistory>> {
+        self.run(&Request::History(query), LoginHistory::from_udp_payload)
+    }
+
+    // Servers are queried at the same time, so that unreachable ones don't delay
+    // the others
+    fn run<T, F>(&self, request: &Request, decode: F) -> Vec<Response<T>>
+    where
+        T: Send,
+        F: Fn(&[u8], &str) -> WhereResult<T> + Sync
+    {
+        let decode = &decode;
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = self.servers.iter()
+                .map(|server| scope.spawn(move || self.query(server, request, decode)))
+                .collect();
+
+            handles.into_iter()
+                .map(|handle| handle.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+                .collect()
+        })
+    }
+
+    fn query<T, F>(&self, server: &Server, request: &Request, decode: &F) -> Response<T>
+    where
+        F: Fn(&[u8], &str) -> WhereResult<T>
+    {
+        let started_at = Instant::now();
+        let (timeout, retries) = self.settings(server);
+
+        let mut attempts = 0;
+        let result = query(&server.endpoint, self.port, timeout, retries, request, |buf| decode(buf, server.label()), &mut attempts);
+
+        Response::new(server, started_at, attempts, result)
+    }
+
+    // The timeout and number of attempts used for a server
+    pub(crate) fn settings(&self, server: &Server) -> (Duration, usize) {
+        (server.timeout.unwrap_or(self.timeout), server.max_retries.unwrap_or(self.max_retries))
+    }
+}
+
+/// Resolves an endpoint to its first IPv4 address, using the default port if
+/// it doesn't include one
+pub fn get_address(endpoint: &str, default_port: u16) -> WhereResult<SocketAddr> {
+    let addresses = match endpoint.to_socket_addrs() {
+        Ok(addresses) => addresses,
+        Err(_) => format!("{endpoint}:{default_port}").to_socket_addrs()?
+    };
+
+    addresses.into_iter()
+        .find(|i| i.is_ipv4())
+        .ok_or_else(|| WhereError::from(io::Error::new(ErrorKind::AddrNotAvailable, format!("No IPv4 address found for {endpoint}"))))
+}
+
+fn create_socket(address: &SocketAddr) -> WhereResult<UdpSocket> {
+    let socket = UdpSocket::bind(if address.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    })?;
+
+    Ok(socket)
+}
+
+// Returns None if the server doesn't answer within the timeout
+fn attempt_fetch<T, F>(socket: &UdpSocket, address: &SocketAddr, timeout: Duration, request: &[u8], buf: &mut [u8], decode: &F) -> WhereResult<Option<(T, Duration)>>
+where
+    F: Fn(&[u8]) -> WhereResult<T>
+{
+    let sent_at = Instant::now();
+    socket.send_to(request, address)?;
+
+    loop {
+        let Some(left) = timeout.checked_sub(sent_at.elapsed()).filter(|left| !left.is_zero()) else {
+            return Ok(None);
+        };
+        socket.set_read_timeout(Some(left))?;
+
+        match socket.recv_from(buf) {
+            // Anyone can send datagrams to the socket, only the server can answer
+            Ok((_, from)) if from != *address => continue,
+            Ok((length, _)) => {
+                let rtt = sent_at.elapsed();
+                let collection = decode(&buf[..length])?;
+                return Ok(Some((collection, rtt)));
+            },
+            Err(e) if e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::WouldBlock => return Ok(None),
+            Err(e) => return Err(WhereError::from(e)),
+        }
+    }
+}
+
+// Sends a request to a server, trying again up to retries times if it doesn't
+// answer within the timeout
+fn query<T, F>(endpoint: &str, default_port: u16, timeout: Duration, retries: usize, request: &Request, decode: F, attempts: &mut usize) -> WhereResult<(T, Duration)>
+where
+    F: Fn(&[u8]) -> WhereResult<T>
+{
+    let request = request.to_udp_payload()?;
+    let address = get_address(endpoint, default_port)?;
+    let socket = create_socket(&address)?;
+    let mut buf = vec![0; MAX_PAYLOAD_LENGTH];
+    let mut decode_error = None;
+
+    for attempt in 1..=retries {
+        *attempts = attempt;
+
+        // Replies that can't be decoded may have been corrupted on the way, so
+        // they are given another attempt like lost ones
+        match attempt_fetch(&socket, &address, timeout, &request, &mut buf, &decode) {
+            Ok(Some(res)) => return Ok(res),
+            Ok(None) => {}
+            Err(e @ WhereError::EncodeDecodeError(_)) => decode_error = Some(e),
+            Err(e) => return Err(e)
+        }
+    }
+
+    Err(decode_error.unwrap_or_else(|| WhereError::TimedOut(endpoint.to_string(), address.to_string(), retries, timeout)))
+}
@@ -1,3 +1,4 @@
+use std::error::Error;
 use std::fmt::Display;
 use std::string::FromUtf8Error;
 use std::{fmt, io};
@@ -5,31 +6,102 @@ use std::net::AddrParseError;
 use std::time::Duration;
 use crate::{MAX_ENTRY_LENGTH, MAX_PAYLOAD_LENGTH};
 
+#[derive(Debug)]
 pub enum WhereError {
     EncodeDecodeError(EncodeDecodeError),
     IOError(io::Error),
+    // The protocol has no authentication of its own, who may ask what is left to
+    // the operating system and the network.  This is how their refusals look,
+    // such as a wtmp file that cannot be read or a firewall rejecting packets,
+    // and kept apart from other I/O errors as retrying will not help.
+    AccessDenied(io::Error),
     TimedOut(String, String, usize, Duration),
     CannotParseAddress(AddrParseError)
 }
 
+#[derive(Debug)]
 pub enum EncodeDecodeError {
     InvalidEntryLength(usize),
     InvalidPayloadLength(usize),
     BadMagic([u8; 4]),
+    // The peer sent another known kind of payload than the expected one, which
+    // happens when it predates what was asked
+    VersionMismatch([u8; 4], [u8; 4]),
     IncorrectEntryCount,
     StringSizeLimitExceeded(u32, usize),
     StringDecodeError(FromUtf8Error),
     NonbinaryBoolean,
     EmptyRemote,
+    InvalidExtensionLength(u8, usize),
+    UnknownRequestKind(u8),
+    TrailingData(usize),
+    FieldTooLong(&'static str, usize, usize),
+    TooManyEntries(usize),
     IOErrorWhileTranscoding(io::Error)
 }
 
 pub type WhereResult<T> = Result<T, WhereError>;
 pub type EncodeDecodeResult<T> = Result<T, EncodeDecodeError>;
 
+// A category of errors that stays the same across versions, so that programs
+// can act on it, such as the exit status of where
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    Io = 1,
+    Protocol = 2,
+    ProtocolVersion = 3,
+    Timeout = 4,
+    Address = 5,
+    AccessDenied = 6
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Io => "io",
+            Self::Protocol => "protocol",
+            Self::ProtocolVersion => "protocol_version",
+            Self::Timeout => "timeout",
+            Self::Address => "address",
+            Self::AccessDenied => "access_denied"
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl WhereError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::EncodeDecodeError(e) => e.code(),
+            Self::IOError(_) => ErrorCode::Io,
+            Self::AccessDenied(_) => ErrorCode::AccessDenied,
+            Self::TimedOut(..) => ErrorCode::Timeout,
+            Self::CannotParseAddress(_) => ErrorCode::Address
+        }
+    }
+}
+
+impl EncodeDecodeError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::VersionMismatch(..) | Self::UnknownRequestKind(_) => ErrorCode::ProtocolVersion,
+            Self::IOErrorWhileTranscoding(_) => ErrorCode::Io,
+            _ => ErrorCode::Protocol
+        }
+    }
+}
+
 impl From<io::Error> for WhereError {
     fn from(value: io::Error) -> Self {
-        Self::IOError(value)
+        match value.kind() {
+            io::ErrorKind::PermissionDenied => Self::AccessDenied(value),
+            _ => Self::IOError(value)
+        }
     }
 }
 
@@ -69,12 +141,18 @@ impl Display for EncodeDecodeError {
             Self::InvalidEntryLength(s) => write!(f, "Invalid entry length: {s} but maximum is {MAX_ENTRY_LENGTH}"),
             Self::InvalidPayloadLength(s) => write!(f, "Invalid full payload length: {s} but maximum is {MAX_PAYLOAD_LENGTH}"),
             Self::BadMagic(m) => write!(f, "Invalid packet magic ({}), possible corruption or invalid server", String::from_utf8_lossy(m)),
-            Self::IncorrectEntryCount => write!(f, "Invalid amount of entries decoded"),
-            Self::StringDecodeError(e) => write!(f, "String decoding error: {e}"),
+            Self::VersionMismatch(expected, found) => write!(f, "Expected a {} payload but got {}, the server may be running another version", String::from_utf8_lossy(expected), String::from_utf8_lossy(found)),
+            Self::IncorrectEntryCount => write!(f, "Payload ends before the number of entries it declares"),
+            Self::StringDecodeError(_) => write!(f, "String decoding error"),
             Self::StringSizeLimitExceeded(curr, max) => write!(f, "Exceeded length limit for payload string ({curr} > {max})"),
             Self::NonbinaryBoolean => write!(f, "Boolean value is not 0 or 1"),
             Self::EmptyRemote => write!(f, "Remote tag set but no remote host is present"),
-            Self::IOErrorWhileTranscoding(e) => write!(f, "Input/output error while encoding/decoding: {e}"),
+            Self::InvalidExtensionLength(kind, length) => write!(f, "Extension block {kind} does not match its declared length of {length} bytes"),
+            Self::UnknownRequestKind(kind) => write!(f, "Unknown request kind: {kind}"),
+            Self::FieldTooLong(field, length, max) => write!(f, "Field {field} is {length} bytes long but maximum is {max}"),
+            Self::TooManyEntries(count) => write!(f, "Too many entries to encode: {count} but maximum is {}", u16::MAX),
+            Self::TrailingData(length) => write!(f, "Unexpected {length} bytes after the end of the payload"),
+            Self::IOErrorWhileTranscoding(_) => write!(f, "Input/output error while encoding/decoding"),
         }
     }
 }
@@ -82,10 +160,59 @@ impl Display for EncodeDecodeError {
 impl Display for WhereError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::EncodeDecodeError(e) => write!(f, "Encode/decode error: {e}"),
-            Self::IOError(e) => write!(f, "Input/output error: {e}"),
+            Self::EncodeDecodeError(_) => write!(f, "Encode/decode error"),
+            Self::IOError(_) => write!(f, "Input/output error"),
+            Self::AccessDenied(_) => write!(f, "Access denied"),
             Self::TimedOut(server, address, max_retry, timeout) => write!(f, "Timed out waiting for data from {server} ({address}) after {max_retry} attempts every {} ms", timeout.as_millis()),
-            Self::CannotParseAddress(e) => write!(f, "Unable to parse server address: {e}")
+            Self::CannotParseAddress(_) => write!(f, "Unable to parse server address")
+        }
+    }
+}
+
+// Messages leave out the error they wrap, which is the source instead, so use
+// Report to print the whole chain
+impl Error for EncodeDecodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::StringDecodeError(e) => Some(e),
+            Self::IOErrorWhileTranscoding(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl Error for WhereError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::EncodeDecodeError(e) => Some(e),
+            Self::IOError(e) | Self::AccessDenied(e) => Some(e),
+            Self::CannotParseAddress(e) => Some(e),
+            Self::TimedOut(..) => None
+        }
+    }
+}
+
+/// Displays an error followed by its sources, separated by colons.
+///
+/// ```
+/// use std::io;
+/// use whrd::error::{Report, WhereError};
+///
+/// let error = WhereError::from(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+/// assert_eq!(Report(&error).to_string(), "Input/output error: no such file");
+/// ```
+pub struct Report<'a>(pub &'a dyn Error);
+
+impl Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+
+        let mut source = self.0.source();
+        while let Some(error) = source {
+            write!(f, ": {error}")?;
+            source = error.source();
         }
+
+        Ok(())
     }
 }
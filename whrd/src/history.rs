@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::Path;
+use coreutils_core::os::utmpx::UtmpxKind;
+
+use crate::error::{EncodeDecodeError, EncodeDecodeResult, WhereResult};
+use crate::parse::PayloadWriter;
+use crate::request::HistoryQuery;
+use crate::source::read_utmpx_file;
+use crate::{parse, Session, EXTENSION_END, MAX_ENTRY_LENGTH, MAX_PAYLOAD_LENGTH};
+
+pub const WHERED_HISTORY_MAGIC: [u8; 4] = *b"WHRH";
+pub const MAX_HISTORY_ENTRY_LENGTH: usize = MAX_ENTRY_LENGTH + 9;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HistoryEntry {
+    pub session: Session,
+    // None if the session is still open, or if the system went down without recording it
+    pub logout_time: Option<i64>
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LoginHistory {
+    #[cfg_attr(feature = "serde", serde(rename = "entries"))]
+    inner: Vec<HistoryEntry>
+}
+
+impl HistoryQuery {
+    pub fn matches(&self, entry: &HistoryEntry) -> bool {
+        // Like last(1), sessions still open at some point of the range count,
+        // not only the ones that started in it
+        let logout_time = entry.logout_time.unwrap_or(i64::MAX);
+        let in_range = entry.session.login_time <= self.until && logout_time >= self.since;
+        let user_matches = self.user.as_ref().is_none_or(|user| *user == entry.session.user);
+
+        in_range && user_matches
+    }
+}
+
+impl HistoryEntry {
+    pub fn duration(&self, now: i64) -> i64 {
+        self.logout_time.unwrap_or(now).saturating_sub(self.session.login_time).max(0)
+    }
+
+    pub fn from_udp_payload(cursor: &mut impl Read, host: &str) -> WhereResult<Self> {
+        let session = Session::from_udp_payload(cursor, host)?;
+
+        let logout_time = if parse::read_bool_field(cursor)? {
+            Some(parse::read_field(cursor, |buf| Ok(i64::from_be_bytes(buf)))?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            session,
+            logout_time
+        })
+    }
+
+    pub fn to_udp_payload(&self) -> EncodeDecodeResult<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(self.encoded_len());
+        self.write_udp_payload(&mut bytes)?;
+
+        Ok(bytes)
+    }
+
+    // How many bytes write_udp_payload writes
+    pub fn encoded_len(&self) -> usize {
+        self.session.encoded_len() + 1 + self.logout_time.map_or(0, |_| 8)
+    }
+
+    pub fn write_udp_payload(&self, writer: &mut impl Write) -> EncodeDecodeResult<()> {
+        self.session.write_udp_payload(writer)?;
+
+        match self.logout_time {
+            None => writer.write_all(&[0u8])?,
+            Some(time) => {
+                writer.write_all(&[1u8])?;
+                writer.write_all(&time.to_be_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl LoginHistory {
+    pub fn from_wtmp_file(path: impl AsRef<Path>, query: &HistoryQuery) -> WhereResult<Self> {
+        // Records are paired in file order, as logins and logouts within the
+        // same second may otherwise be seen the wrong way round
+        let records = read_utmpx_file(path.as_ref())?;
+
+        let mut entries: Vec<HistoryEntry> = vec![];
+        let mut open: HashMap<String, usize> = HashMap::new();
+
+        for record in records {
+            let time = record.timeval().tv_sec;
+
+            match record.entry_type() {
+                UtmpxKind::UserProcess => {
+                    let line = record.device_name().to_string();
+
+                    // A new login on the same line means we missed the logout
+                    if let Some(index) = open.insert(line, entries.len()) {
+                        entries[index].logout_time = Some(time);
+                    }
+
+                    entries.push(HistoryEntry {
+                        session: Session::from_record(&record),
+                        logout_time: None
+                    });
+                }
+                UtmpxKind::DeadProcess => {
+                    if let Some(index) = open.remove(&record.device_name().to_string()) {
+                        entries[index].logout_time = Some(time);
+                    }
+                }
+                // Every session ends when the system goes down
+                UtmpxKind::BootTime | UtmpxKind::ShutdownProcess => {
+                    for (_, index) in open.drain() {
+                        entries[index].logout_time = Some(time);
+                    }
+                }
+                UtmpxKind::RunLevel if record.user() == "shutdown" => {
+                    for (_, index) in open.drain() {
+                        entries[index].logout_time = Some(time);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let inner = entries.into_iter()
+            .filter(|entry| query.matches(entry))
+            .map(|mut entry| {
+                entry.session.active = entry.logout_time.is_none();
+                entry
+            })
+            .collect();
+
+        Ok(Self {
+            inner
+        })
+    }
+
+    pub fn get_empty() -> Self {
+        Self {
+            inner: vec![]
+        }
+    }
+
+    pub fn from_vec(entries: Vec<HistoryEntry>) -> Self {
+        Self {
+            inner: entries
+        }
+    }
+
+    pub fn push(&mut self, entry: HistoryEntry) {
+        self.inner.push(entry);
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, HistoryEntry> {
+        self.inner.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, HistoryEntry> {
+        self.inner.iter_mut()
+    }
+
+    pub fn retain<F>(&mut self, filter: F)
+    where
+        F: FnMut(&HistoryEntry) -> bool
+    {
+        self.inner.retain(filter);
+    }
+
+    pub fn into_vec(self) -> Vec<HistoryEntry> {
+        self.inner
+    }
+
+    pub fn to_udp_payload(&self) -> EncodeDecodeResult<Vec<u8>> {
+        let mut bytes: Vec<u8> = vec![];
+        self.write_udp_payload(&mut bytes)?;
+
+        Ok(bytes)
+    }
+
+    // History can be much longer than what fits in a datagram, so only the
+    // most recent logins are kept when that happens.
+    pub fn write_udp_payload(&self, writer: &mut impl Write) -> EncodeDecodeResult<usize> {
+        let mut entries: Vec<&HistoryEntry> = self.inner.iter().collect();
+        entries.sort_by_key(|entry| entry.session.login_time);
+
+        let mut length = WHERED_HISTORY_MAGIC.len() + 2 + 1;
+        let mut first = entries.len();
+
+        for entry in entries.iter().rev() {
+            entry.session.validate()?;
+            let entry_length = entry.encoded_len();
+
+            if entry_length > MAX_HISTORY_ENTRY_LENGTH {
+                return Err(EncodeDecodeError::InvalidEntryLength(entry_length));
+            }
+
+            if length + entry_length > MAX_PAYLOAD_LENGTH || entries.len() - first == u16::MAX as usize {
+                break;
+            }
+
+            length += entry_length;
+            first -= 1;
+        }
+
+        let entries = &entries[first..];
+        let mut writer = PayloadWriter::new(writer, MAX_PAYLOAD_LENGTH);
+
+        writer.write(&WHERED_HISTORY_MAGIC)?;
+        writer.write(&(entries.len() as u16).to_be_bytes())?;
+
+        for entry in entries {
+            writer.write_sized(entry.encoded_len(), |inner| entry.write_udp_payload(inner))?;
+        }
+
+        writer.write(&[EXTENSION_END])?;
+
+        Ok(writer.written())
+    }
+
+    // Decodes a whole datagram, which must not contain anything after the payload
+    pub fn from_udp_payload(buffer: &[u8], host: &str) -> WhereResult<Self> {
+        parse::read_payload(buffer, |reader| Self::from_reader(reader, host))
+    }
+
+    // Decodes a payload from a stream, leaving anything after it unread
+    pub fn from_reader(reader: &mut impl Read, host: &str) -> WhereResult<Self> {
+        parse::read_magic(reader, WHERED_HISTORY_MAGIC)?;
+
+        let entry_count = parse::read_field(reader, |buf| Ok(u16::from_be_bytes(buf)))?;
+        let inner = parse::read_entries(reader, entry_count, |reader| HistoryEntry::from_udp_payload(reader, host))?;
+
+        parse::read_extensions(reader, |_, _| Ok(()))?;
+
+        Ok(Self {
+            inner
+        })
+    }
+}
+
+impl IntoIterator for LoginHistory {
+    type Item = HistoryEntry;
+    type IntoIter = std::vec::IntoIter<HistoryEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a LoginHistory {
+    type Item = &'a HistoryEntry;
+    type IntoIter = std::slice::Iter<'a, HistoryEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.iter()
+    }
+}
+
+impl FromIterator<HistoryEntry> for LoginHistory {
+    fn from_iter<I: IntoIterator<Item = HistoryEntry>>(iter: I) -> Self {
+        Self::from_vec(iter.into_iter().collect())
+    }
+}
@@ -0,0 +1,158 @@
+use std::io::{self, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use coreutils_core::libc;
+use coreutils_core::os::utsname::UtsName;
+
+use crate::error::{EncodeDecodeResult, WhereResult};
+use crate::{parse, MAX_OS_LENGTH, MAX_REMOTE_LENGTH};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HostInfo {
+    pub hostname: String,
+    pub os: String,
+    pub uptime: u64,
+    // Load averages over 1, 5 and 15 minutes, in hundredths (like rwhod does)
+    pub load_average: [u32; 3],
+    pub cpus: u16,
+    pub boot_time: i64,
+}
+
+impl HostInfo {
+    pub fn system() -> io::Result<Self> {
+        let uts = UtsName::new()?;
+
+        let mut hostname = uts.node_name().to_string();
+        parse::truncate_string(&mut hostname, MAX_REMOTE_LENGTH);
+
+        let mut os = format!("{} {}", uts.system_name(), uts.release());
+        parse::truncate_string(&mut os, MAX_OS_LENGTH);
+
+        let load_average = get_load_average()?.map(|load| (load * 100.0).round() as u32);
+        let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) }.clamp(0, u16::MAX as libc::c_long) as u16;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        let boot_time = get_boot_time(now)?;
+        let uptime = (now - boot_time).max(0) as u64;
+
+        Ok(Self {
+            hostname,
+            os,
+            uptime,
+            load_average,
+            cpus,
+            boot_time,
+        })
+    }
+
+    pub fn load_average_f64(&self) -> [f64; 3] {
+        self.load_average.map(|load| load as f64 / 100.0)
+    }
+
+    pub fn from_udp_payload(cursor: &mut impl Read) -> WhereResult<Self> {
+        let hostname = parse::read_string_field(cursor, MAX_REMOTE_LENGTH as u32)?;
+        let os = parse::read_string_field(cursor, MAX_OS_LENGTH as u32)?;
+        let uptime = parse::read_field(cursor, |buf| Ok(u64::from_be_bytes(buf)))?;
+        let load_average = [
+            parse::read_field(cursor, |buf| Ok(u32::from_be_bytes(buf)))?,
+            parse::read_field(cursor, |buf| Ok(u32::from_be_bytes(buf)))?,
+            parse::read_field(cursor, |buf| Ok(u32::from_be_bytes(buf)))?,
+        ];
+        let cpus = parse::read_field(cursor, |buf| Ok(u16::from_be_bytes(buf)))?;
+        let boot_time = parse::read_field(cursor, |buf| Ok(i64::from_be_bytes(buf)))?;
+
+        Ok(Self {
+            hostname,
+            os,
+            uptime,
+            load_average,
+            cpus,
+            boot_time,
+        })
+    }
+
+    pub fn to_udp_payload(&self) -> EncodeDecodeResult<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(self.encoded_len());
+        self.write_udp_payload(&mut bytes)?;
+
+        Ok(bytes)
+    }
+
+    pub fn validate(&self) -> EncodeDecodeResult<()> {
+        parse::check_length("hostname", &self.hostname, MAX_REMOTE_LENGTH)?;
+        parse::check_length("os", &self.os, MAX_OS_LENGTH)
+    }
+
+    // How many bytes write_udp_payload writes
+    pub fn encoded_len(&self) -> usize {
+        4 + self.hostname.len() + 4 + self.os.len() + 8 + 4 * 3 + 2 + 8
+    }
+
+    pub fn write_udp_payload(&self, writer: &mut impl Write) -> EncodeDecodeResult<()> {
+        self.validate()?;
+
+        writer.write_all(&(self.hostname.len() as u32).to_be_bytes())?;
+        writer.write_all(self.hostname.as_bytes())?;
+        writer.write_all(&(self.os.len() as u32).to_be_bytes())?;
+        writer.write_all(self.os.as_bytes())?;
+        writer.write_all(&self.uptime.to_be_bytes())?;
+
+        for load in self.load_average {
+            writer.write_all(&load.to_be_bytes())?;
+        }
+
+        writer.write_all(&self.cpus.to_be_bytes())?;
+        writer.write_all(&self.boot_time.to_be_bytes())?;
+
+        Ok(())
+    }
+}
+
+// Called directly, as the wrapper in coreutils_core changed its signature
+// between patch releases
+fn get_load_average() -> io::Result<[f64; 3]> {
+    let mut load_average = [0.0; 3];
+
+    if unsafe { libc::getloadavg(load_average.as_mut_ptr(), 3) } != 3 {
+        return Err(io::Error::other("Unable to read the load average"));
+    }
+
+    Ok(load_average)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn get_boot_time(now: i64) -> io::Result<i64> {
+    let mut info: libc::sysinfo = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::sysinfo(&mut info) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(now - info.uptime as i64)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn get_boot_time(_now: i64) -> io::Result<i64> {
+    let mut boot_time: libc::timeval = unsafe { std::mem::zeroed() };
+    let mut size = std::mem::size_of::<libc::timeval>();
+    let mut mib = [libc::CTL_KERN, libc::KERN_BOOTTIME];
+
+    let res = unsafe {
+        libc::sysctl(
+            mib.as_mut_ptr(),
+            mib.len() as libc::c_uint,
+            &mut boot_time as *mut libc::timeval as *mut libc::c_void,
+            &mut size,
+            std::ptr::null_mut(),
+            0
+        )
+    };
+
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(boot_time.tv_sec as i64)
+}
@@ -1,23 +1,44 @@
-use std::io::Cursor;
-use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::path::Path;
 use coreutils_core::os::utmpx::*;
 
 use crate::error::{WhereResult, EncodeDecodeResult, EncodeDecodeError};
+use crate::parse::PayloadWriter;
 
 mod parse;
+pub mod client;
+#[cfg(feature = "tokio")]
+pub mod asynchronous;
+mod history;
+mod host;
+mod request;
+mod source;
 pub mod error;
 
+pub use history::{HistoryEntry, LoginHistory, MAX_HISTORY_ENTRY_LENGTH, WHERED_HISTORY_MAGIC};
+pub use host::HostInfo;
+pub use request::{HistoryQuery, Request, MAX_REQUEST_LENGTH};
+pub use source::{SessionSource, UtmpFileSource, UtmpxSource};
+
 pub const WHERED_MAGIC: [u8; 4] = *b"WHRD";
 pub const MAX_USER_TTY_LENGTH: usize = 32;
 pub const MAX_REMOTE_LENGTH: usize = 64;
-pub const MAX_ENTRY_LENGTH: usize = MAX_REMOTE_LENGTH + MAX_USER_TTY_LENGTH * 2 + 25;
+pub const MAX_OS_LENGTH: usize = 64;
+pub const MAX_CONTAINER_LENGTH: usize = 64;
+// The strings, their lengths, the PID, login time, remote tag and active flag
+pub const MAX_ENTRY_LENGTH: usize = MAX_REMOTE_LENGTH + MAX_USER_TTY_LENGTH * 2 + 4 * 3 + 4 + 8 + 1 + 1;
 pub const MAX_PAYLOAD_LENGTH: usize = 65501;
 pub const MAX_PAYLOAD_ENTRIES: usize = MAX_PAYLOAD_LENGTH / MAX_ENTRY_LENGTH;
 
-type Payload = [u8; MAX_PAYLOAD_LENGTH];
-type PayloadCursor = Cursor<Payload>;
+// Extension blocks are appended after the session entries so that older
+// clients, which stop reading after the last entry, can safely ignore them.
+pub(crate) const EXTENSION_END: u8 = 0;
+const EXTENSION_HOST_INFO: u8 = 1;
+const EXTENSION_CONTAINERS: u8 = 2;
+const EXTENSION_HOSTS: u8 = 3;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Session {
     pub host: Option<String>,
     pub pid: i32,
@@ -26,89 +47,298 @@ pub struct Session {
     pub tty: String,
     pub remote: Option<String>,
     pub active: bool,
+    pub container: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SessionCollection {
-    inner: Vec<Session>
+    #[cfg_attr(feature = "serde", serde(rename = "sessions"))]
+    inner: Vec<Session>,
+    host_info: Option<HostInfo>
 }
 
 impl SessionCollection {
     pub fn fetch() -> Self {
-        let inner: Vec<Session> = UtmpxSet::system()
+        Self::fetch_filtered(|_| true)
+    }
+
+    // The sessions of this host. Errors can't be returned, so they leave the
+    // list empty, fetch_from can be used to tell them apart.
+    pub fn fetch_filtered<F>(filter: F) -> Self
+    where
+        F: Fn(&Session) -> bool
+    {
+        Self::fetch_from(&UtmpxSource, filter)
+            .unwrap_or_else(|_| Self::get_empty())
+            .with_host_info(HostInfo::system().ok())
+    }
+
+    pub fn fetch_from<S, F>(source: &S, filter: F) -> WhereResult<Self>
+    where
+        S: SessionSource + ?Sized,
+        F: Fn(&Session) -> bool
+    {
+        let inner: Vec<Session> = source.sessions()?
             .into_iter()
-            .filter(|utmpx| utmpx.entry_type() == UtmpxKind::UserProcess || utmpx.entry_type() == UtmpxKind::DeadProcess)
-            .map(Session::from)
+            .filter(filter)
             .collect();
 
-        Self {
-            inner
-        }
+        // Sources may read the sessions of other hosts, such as containers or
+        // relayed servers, so host information is left to the caller
+        Ok(Self::from_vec(inner))
     }
     
+    pub fn from_utmp_file(path: impl AsRef<Path>) -> WhereResult<Self> {
+        // The file may come from another host, such as a container or a
+        // backup, so the information of this one would be misleading
+        Ok(Self::from_vec(UtmpFileSource::new(path).sessions()?))
+    }
+
     pub fn get_empty() -> Self {
         Self {
-            inner: vec![]
+            inner: vec![],
+            host_info: None
+        }
+    }
+
+    pub fn from_vec(sessions: Vec<Session>) -> Self {
+        Self::from_parts(sessions, None)
+    }
+
+    pub fn from_parts(sessions: Vec<Session>, host_info: Option<HostInfo>) -> Self {
+        Self {
+            inner: sessions,
+            host_info
         }
     }
 
+    pub fn host_info(&self) -> Option<&HostInfo> {
+        self.host_info.as_ref()
+    }
+
+    pub fn host_info_mut(&mut self) -> Option<&mut HostInfo> {
+        self.host_info.as_mut()
+    }
+
+    pub fn set_host_info(&mut self, host_info: Option<HostInfo>) {
+        self.host_info = host_info;
+    }
+
+    pub fn with_host_info(mut self, host_info: Option<HostInfo>) -> Self {
+        self.host_info = host_info;
+        self
+    }
+
+    pub fn push(&mut self, session: Session) {
+        self.inner.push(session);
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Session> {
+        self.inner.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Session> {
+        self.inner.iter_mut()
+    }
+
     pub fn into_vec(self) -> Vec<Session> {
         self.inner
     }
 
-    pub fn to_udp_payload(self) -> EncodeDecodeResult<Vec<u8>> {
-        println!("Encoding payload with {} entries", self.inner.len());
+    pub fn into_parts(self) -> (Vec<Session>, Option<HostInfo>) {
+        (self.inner, self.host_info)
+    }
 
+    pub fn to_udp_payload(&self) -> EncodeDecodeResult<Vec<u8>> {
         let mut bytes: Vec<u8> = vec![];
-        bytes.extend(&WHERED_MAGIC);
+        self.write_udp_payload(&mut bytes)?;
+
+        Ok(bytes)
+    }
+
+    // Writes the payload without consuming the collection, such as into a buffer
+    // reused between requests, and returns its length. Limits are checked before
+    // each part is written, so on error only the parts before it are.
+    pub fn write_udp_payload(&self, writer: &mut impl Write) -> EncodeDecodeResult<usize> {
+        let mut writer = PayloadWriter::new(writer, MAX_PAYLOAD_LENGTH);
+
+        let entry_count = u16::try_from(self.inner.len()).map_err(|_| EncodeDecodeError::TooManyEntries(self.inner.len()))?;
 
-        let entry_count = (self.inner.len() as u16).to_be_bytes();
-        bytes.extend(&entry_count);
+        writer.write(&WHERED_MAGIC)?;
+        writer.write(&entry_count.to_be_bytes())?;
 
-        for item in self.inner {
-            let entry = item.to_udp_payload();
+        for session in &self.inner {
+            // Fields that are too long are reported as such, rather than as a long entry
+            session.validate()?;
+            let length = session.encoded_len();
 
-            if entry.len() > MAX_ENTRY_LENGTH {
-                return Err(EncodeDecodeError::InvalidEntryLength(entry.len()));
+            if length > MAX_ENTRY_LENGTH {
+                return Err(EncodeDecodeError::InvalidEntryLength(length));
             }
 
-            bytes.extend(entry);
+            writer.write_sized(length, |inner| session.write_udp_payload(inner))?;
         }
 
-        if bytes.len() > MAX_PAYLOAD_LENGTH {
-            Err(EncodeDecodeError::InvalidPayloadLength(bytes.len()))
-        } else {
-            Ok(bytes)
+        if let Some(host_info) = &self.host_info {
+            host_info.validate()?;
+            let length = host_info.encoded_len();
+            writer.write_extension(EXTENSION_HOST_INFO, length, |writer| {
+                writer.write_sized(length, |inner| host_info.write_udp_payload(inner))
+            })?;
+        }
+
+        if self.inner.iter().any(|s| s.container.is_some()) {
+            let containers = || self.inner.iter().map(|s| s.container.as_deref());
+            writer.write_extension(EXTENSION_CONTAINERS, parse::string_list_len(containers()), |writer| {
+                parse::write_string_list(writer, "container", MAX_CONTAINER_LENGTH, containers())
+            })?;
         }
+
+        // Only relayed sessions have a host, which is the one they originate from
+        if self.inner.iter().any(|s| s.host.is_some()) {
+            let hosts = || self.inner.iter().map(|s| s.host.as_deref());
+            writer.write_extension(EXTENSION_HOSTS, parse::string_list_len(hosts()), |writer| {
+                parse::write_string_list(writer, "host", MAX_REMOTE_LENGTH, hosts())
+            })?;
+        }
+
+        writer.write(&[EXTENSION_END])?;
+
+        Ok(writer.written())
     }
 
-    pub fn from_udp_payload(buffer: Payload, host: &str) -> WhereResult<Self> {
-        let mut cursor = Cursor::new(buffer);
-        let mut inner = vec![];
+    // Decodes a whole datagram, which must not contain anything after the payload
+    pub fn from_udp_payload(buffer: &[u8], host: &str) -> WhereResult<Self> {
+        parse::read_payload(buffer, |reader| Self::from_reader(reader, host))
+    }
 
-        // Check magic
-        parse::read_field(&mut cursor, |buf| {
-            if buf != WHERED_MAGIC {
-                Err(EncodeDecodeError::BadMagic(buf))?
-            } else {
-                Ok(())
+    // Decodes a payload from a stream, leaving anything after it unread
+    pub fn from_reader(reader: &mut impl Read, host: &str) -> WhereResult<Self> {
+        parse::read_magic(reader, WHERED_MAGIC)?;
+
+        let entry_count = parse::read_field(reader, |buf| Ok(u16::from_be_bytes(buf)))?;
+        let mut inner = parse::read_entries(reader, entry_count, |reader| Session::from_udp_payload(reader, host))?;
+
+        let mut host_info = None;
+
+        parse::read_extensions(reader, |kind, block| {
+            match kind {
+                EXTENSION_HOST_INFO => host_info = Some(HostInfo::from_udp_payload(block)?),
+                EXTENSION_CONTAINERS => {
+                    let containers = parse::read_string_list(block, entry_count, MAX_CONTAINER_LENGTH as u32)?;
+
+                    for (session, container) in inner.iter_mut().zip(containers) {
+                        session.container = container;
+                    }
+                }
+                EXTENSION_HOSTS => {
+                    let hosts = parse::read_string_list(block, entry_count, MAX_REMOTE_LENGTH as u32)?;
+
+                    // Sessions relayed from another server keep the name of their host
+                    for (session, host) in inner.iter_mut().zip(hosts) {
+                        if host.is_some() {
+                            session.host = host;
+                        }
+                    }
+                }
+                _ => {}
             }
-        })?;
-
-        let entry_count = parse::read_field(&mut cursor, |buf| Ok(u16::from_be_bytes(buf)))?;
 
-        for _ in 0..entry_count {
-            inner.push(Session::from_udp_payload(&mut cursor, host)?);
-        }
+            Ok(())
+        })?;
 
         Ok(Self {
-            inner
+            inner,
+            host_info
         })
     }
 }
 
+impl IntoIterator for SessionCollection {
+    type Item = Session;
+    type IntoIter = std::vec::IntoIter<Session>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a SessionCollection {
+    type Item = &'a Session;
+    type IntoIter = std::slice::Iter<'a, Session>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut SessionCollection {
+    type Item = &'a mut Session;
+    type IntoIter = std::slice::IterMut<'a, Session>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.iter_mut()
+    }
+}
+
+impl FromIterator<Session> for SessionCollection {
+    fn from_iter<I: IntoIterator<Item = Session>>(iter: I) -> Self {
+        Self::from_vec(iter.into_iter().collect())
+    }
+}
+
+impl Extend<Session> for SessionCollection {
+    fn extend<I: IntoIterator<Item = Session>>(&mut self, iter: I) {
+        self.inner.extend(iter);
+    }
+}
+
 impl Session {
-    pub fn from_udp_payload(cursor: &mut PayloadCursor, host: &str) -> WhereResult<Self> {
+    // An active local session, to be completed with the other fields or the
+    // with_ methods below
+    pub fn new(user: impl Into<String>, tty: impl Into<String>, login_time: i64) -> Self {
+        Self {
+            host: None,
+            pid: 0,
+            login_time,
+            user: user.into(),
+            tty: tty.into(),
+            remote: None,
+            active: true,
+            container: None
+        }
+    }
+
+    pub fn with_pid(mut self, pid: i32) -> Self {
+        self.pid = pid;
+        self
+    }
+
+    pub fn with_remote(mut self, remote: impl Into<String>) -> Self {
+        self.remote = Some(remote.into());
+        self
+    }
+
+    pub fn with_active(mut self, active: bool) -> Self {
+        self.active = active;
+        self
+    }
+
+    pub fn with_container(mut self, container: impl Into<String>) -> Self {
+        self.container = Some(container.into());
+        self
+    }
+
+    pub fn from_udp_payload(cursor: &mut impl Read, host: &str) -> WhereResult<Self> {
         let pid = parse::read_field(cursor, |buf| Ok(i32::from_be_bytes(buf)))?;
         let login_time = parse::read_field(cursor, |buf| Ok(i64::from_be_bytes(buf)))?;
         let user = parse::read_string_field(cursor, MAX_USER_TTY_LENGTH as u32)?;
@@ -117,7 +347,12 @@ impl Session {
         let remote = {
             let has_remote_tag = parse::read_bool_field(cursor)?;
             if has_remote_tag {
-                Some(parse::read_string_field(cursor, MAX_REMOTE_LENGTH as u32)?)
+                let remote = parse::read_string_field(cursor, MAX_REMOTE_LENGTH as u32)?;
+                if remote.is_empty() {
+                    return Err(EncodeDecodeError::EmptyRemote.into());
+                }
+
+                Some(remote)
             } else {
                 None
             }
@@ -135,58 +370,109 @@ impl Session {
             tty,
             remote,
             active,
+            container: None,
         })
     }
 
-    pub fn to_udp_payload(self) -> Vec<u8> {
-        let mut bytes: Vec<u8> = vec![];
+    pub fn to_udp_payload(&self) -> EncodeDecodeResult<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(self.encoded_len());
+        self.write_udp_payload(&mut bytes)?;
 
-        let pid = self.pid.to_be_bytes();
-        let login_time = self.login_time.to_be_bytes();
-        let user_length = (self.user.len() as u32).to_be_bytes();
-        let user = self.user.as_bytes();
-        let tty_length = (self.tty.len() as u32).to_be_bytes();
-        let tty = self.tty.as_bytes();
-        let active = self.active as u8;
-
-        bytes.extend(&pid);
-        bytes.extend(&login_time);
-        bytes.extend(&user_length);
-        bytes.extend(user);
-        bytes.extend(&tty_length);
-        bytes.extend(tty);
-
-        match self.remote {
-            None => bytes.push(0u8),
-            Some(host) => {
-                let host_bytes = host.into_bytes();
-                let host_length = (host_bytes.len() as u32).to_be_bytes();
+        Ok(bytes)
+    }
+
+    // Checks the fields of the entry against the limits of the decoder. The
+    // container and host are checked when a collection is encoded, as they are
+    // sent in extension blocks.
+    pub fn validate(&self) -> EncodeDecodeResult<()> {
+        parse::check_length("user", &self.user, MAX_USER_TTY_LENGTH)?;
+        parse::check_length("tty", &self.tty, MAX_USER_TTY_LENGTH)?;
+        parse::check_length("remote", self.remote.as_deref().unwrap_or_default(), MAX_REMOTE_LENGTH)
+    }
 
-                bytes.push(1u8);
-                bytes.extend(&host_length);
-                bytes.extend(&host_bytes);
+    // Shortens the fields that are too long to be sent, such as ones read from
+    // another source than utmp
+    pub fn truncate_to_limits(&mut self) {
+        parse::truncate_string(&mut self.user, MAX_USER_TTY_LENGTH);
+        parse::truncate_string(&mut self.tty, MAX_USER_TTY_LENGTH);
+
+        for (field, max_length) in [(&mut self.remote, MAX_REMOTE_LENGTH), (&mut self.container, MAX_CONTAINER_LENGTH), (&mut self.host, MAX_REMOTE_LENGTH)] {
+            if let Some(string) = field {
+                parse::truncate_string(string, max_length);
             }
         }
+    }
 
-        bytes.push(active);
+    // How many bytes write_udp_payload writes
+    pub fn encoded_len(&self) -> usize {
+        let remote = self.sent_remote().map_or(0, |remote| 4 + remote.len());
 
-        bytes
+        4 + 8 + 4 + self.user.len() + 4 + self.tty.len() + 1 + remote + 1
+    }
+
+    pub fn write_udp_payload(&self, writer: &mut impl Write) -> EncodeDecodeResult<()> {
+        self.validate()?;
+
+        writer.write_all(&self.pid.to_be_bytes())?;
+        writer.write_all(&self.login_time.to_be_bytes())?;
+        writer.write_all(&(self.user.len() as u32).to_be_bytes())?;
+        writer.write_all(self.user.as_bytes())?;
+        writer.write_all(&(self.tty.len() as u32).to_be_bytes())?;
+        writer.write_all(self.tty.as_bytes())?;
+
+        match self.sent_remote() {
+            None => writer.write_all(&[0u8])?,
+            Some(host) => {
+                writer.write_all(&[1u8])?;
+                writer.write_all(&(host.len() as u32).to_be_bytes())?;
+                writer.write_all(host.as_bytes())?;
+            }
+        }
+
+        writer.write_all(&[self.active as u8])?;
+
+        Ok(())
+    }
+
+    // An empty remote host is sent as a local session, which decoders would
+    // reject otherwise
+    fn sent_remote(&self) -> Option<&str> {
+        self.remote.as_deref().filter(|remote| !remote.is_empty())
     }
 }
 
 impl From<Utmpx> for Session {
     fn from(utmpx: Utmpx) -> Self {
+        Self::from_utmpx(&utmpx, Path::new("/dev"))
+    }
+}
+
+impl Session {
+    pub(crate) fn from_utmpx(utmpx: &Utmpx, dev_dir: &Path) -> Self {
+        let mut session = Self::from_record(utmpx);
+
+        // Work around a bug in Utmpx causing killed sessions to show as
+        // active when they are not.
+        let mut path = dev_dir.to_path_buf();
+        path.push(utmpx.device_name().to_string());
+        session.active = utmpx.entry_type() == UtmpxKind::UserProcess && path.exists();
+
+        session
+    }
+
+    // Converts the fields shared by utmpx and wtmp records, leaving the session inactive
+    pub(crate) fn from_record(utmpx: &Utmpx) -> Self {
         // BStr doesn't have a known size at compile time, so we can't use it instead of String
         let mut host = utmpx.host().to_string();
-        host.truncate(MAX_REMOTE_LENGTH);
+        parse::truncate_string(&mut host, MAX_REMOTE_LENGTH);
 
         let mut user = utmpx.user().to_string();
-        user.truncate(MAX_USER_TTY_LENGTH);
+        parse::truncate_string(&mut user, MAX_USER_TTY_LENGTH);
 
         let pid = utmpx.process_id();
         // In the case of a user session, this will always be a TTY
         let mut tty = utmpx.device_name().to_string();
-        tty.truncate(MAX_USER_TTY_LENGTH);
+        parse::truncate_string(&mut tty, MAX_USER_TTY_LENGTH);
 
         let remote = if host.is_empty() {
             None
@@ -194,11 +480,6 @@ impl From<Utmpx> for Session {
             Some(host)
         };
 
-        // Work around a bug in Utmpx causing killed sessions to show as
-        // active when they are not.
-        let mut path = PathBuf::from("/dev");
-        path.push(utmpx.device_name().to_string());
-        let active = utmpx.entry_type() == UtmpxKind::UserProcess && path.exists();
         let login_time = utmpx.timeval().tv_sec;
 
         Self {
@@ -207,8 +488,9 @@ impl From<Utmpx> for Session {
             pid,
             tty,
             remote,
-            active,
-            login_time
+            active: false,
+            login_time,
+            container: None
         }
     }
 }
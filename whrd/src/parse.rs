@@ -1,9 +1,69 @@
-use std::io::Read;
+use std::io::{self, ErrorKind, Read, Take, Write};
 
-use crate::error::{EncodeDecodeError, WhereError, WhereResult};
-use crate::PayloadCursor;
+use crate::error::{EncodeDecodeError, EncodeDecodeResult, WhereError, WhereResult};
+use crate::{EXTENSION_END, WHERED_HISTORY_MAGIC, WHERED_MAGIC};
 
-pub fn read_field<const N: usize, F, T>(cursor: &mut PayloadCursor, convert_func: F) -> WhereResult<T>
+// Writes a payload, refusing anything that would make it longer than the limit
+// before writing it
+pub struct PayloadWriter<W: Write> {
+    inner: W,
+    written: usize,
+    limit: usize
+}
+
+impl<W: Write> PayloadWriter<W> {
+    pub fn new(inner: W, limit: usize) -> Self {
+        Self {
+            inner,
+            written: 0,
+            limit
+        }
+    }
+
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    fn reserve(&self, length: usize) -> EncodeDecodeResult<()> {
+        if self.written + length > self.limit {
+            Err(EncodeDecodeError::InvalidPayloadLength(self.written + length))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> EncodeDecodeResult<()> {
+        self.write_sized(bytes.len(), |inner| Ok(inner.write_all(bytes)?))
+    }
+
+    // Writes something whose encoded length is known beforehand
+    pub fn write_sized<F>(&mut self, length: usize, write_func: F) -> EncodeDecodeResult<()>
+    where
+        F: FnOnce(&mut W) -> EncodeDecodeResult<()>
+    {
+        self.reserve(length)?;
+        write_func(&mut self.inner)?;
+        self.written += length;
+
+        Ok(())
+    }
+
+    // Extension blocks are only written if they fit whole
+    pub fn write_extension<F>(&mut self, kind: u8, length: usize, write_func: F) -> EncodeDecodeResult<()>
+    where
+        F: FnOnce(&mut Self) -> EncodeDecodeResult<()>
+    {
+        self.reserve(3 + length)?;
+
+        let length = u16::try_from(length).map_err(|_| EncodeDecodeError::InvalidPayloadLength(self.written + 3 + length))?;
+        self.write(&[kind])?;
+        self.write(&length.to_be_bytes())?;
+
+        write_func(self)
+    }
+}
+
+pub fn read_field<const N: usize, F, T>(cursor: &mut impl Read, convert_func: F) -> WhereResult<T>
 where
     F: Fn([u8; N]) -> WhereResult<T>
 {
@@ -14,7 +74,7 @@ where
     Ok(value)
 }
 
-pub fn read_field_dynamic<F, T>(cursor: &mut PayloadCursor, size: usize, convert_func: F) -> WhereResult<T>
+pub fn read_field_dynamic<F, T>(cursor: &mut impl Read, size: usize, convert_func: F) -> WhereResult<T>
 where
     F: Fn(Vec<u8>) -> WhereResult<T>
 {
@@ -25,12 +85,16 @@ where
     Ok(value)
 }
 
-pub fn read_bool_field(cursor: &mut PayloadCursor) -> WhereResult<bool> {
-    let value = read_field::<1, _, _>(cursor, |buf| Ok(buf[0] == 1))?;
+pub fn read_bool_field(cursor: &mut impl Read) -> WhereResult<bool> {
+    let value = read_field::<1, _, _>(cursor, |buf| match buf[0] {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(EncodeDecodeError::NonbinaryBoolean.into())
+    })?;
     Ok(value)
 }
 
-pub fn read_string_field(cursor: &mut PayloadCursor, max_length: u32) -> WhereResult<String> {
+pub fn read_string_field(cursor: &mut impl Read, max_length: u32) -> WhereResult<String> {
     let string_length = read_field(cursor, |buf| Ok(u32::from_be_bytes(buf)))?;
 
     if string_length > max_length {
@@ -41,3 +105,127 @@ pub fn read_string_field(cursor: &mut PayloadCursor, max_length: u32) -> WhereRe
 
     Ok(string)
 }
+
+pub fn read_magic(cursor: &mut impl Read, magic: [u8; 4]) -> WhereResult<()> {
+    read_field(cursor, |buf| {
+        if buf == magic {
+            Ok(())
+        } else if buf == WHERED_MAGIC || buf == WHERED_HISTORY_MAGIC {
+            Err(EncodeDecodeError::VersionMismatch(magic, buf))?
+        } else {
+            Err(EncodeDecodeError::BadMagic(buf))?
+        }
+    })
+}
+
+// Decodes a whole datagram, which must end right where the decoder stops
+pub fn read_payload<T, F>(buffer: &[u8], decode: F) -> WhereResult<T>
+where
+    F: FnOnce(&mut &[u8]) -> WhereResult<T>
+{
+    let mut reader = buffer;
+    let value = decode(&mut reader)?;
+
+    if !reader.is_empty() {
+        Err(EncodeDecodeError::TrailingData(reader.len()))?
+    } else {
+        Ok(value)
+    }
+}
+
+// Reads the number of entries a payload declares, failing if it ends before them
+pub fn read_entries<R, F, T>(reader: &mut R, count: u16, mut read_entry: F) -> WhereResult<Vec<T>>
+where
+    R: Read,
+    F: FnMut(&mut R) -> WhereResult<T>
+{
+    (0..count)
+        .map(|_| match read_entry(reader) {
+            Err(WhereError::IOError(e)) if e.kind() == ErrorKind::UnexpectedEof => Err(EncodeDecodeError::IncorrectEntryCount)?,
+            res => res
+        })
+        .collect()
+}
+
+pub fn read_extensions<R, F>(reader: &mut R, mut handle_func: F) -> WhereResult<()>
+where
+    R: Read,
+    F: FnMut(u8, &mut Take<&mut R>) -> WhereResult<()>
+{
+    loop {
+        // Servers that predate extension blocks end the payload after the last entry
+        let mut kind = [0u8; 1];
+        if reader.read(&mut kind)? == 0 || kind[0] == EXTENSION_END {
+            return Ok(());
+        }
+
+        let kind = kind[0];
+        let length = read_field(reader, |buf| Ok(u16::from_be_bytes(buf)))?;
+        let mut block = reader.take(length as u64);
+
+        // Either the data overflows the block, or the payload ends before it does
+        match handle_func(kind, &mut block) {
+            Err(WhereError::IOError(e)) if e.kind() == ErrorKind::UnexpectedEof => {
+                return Err(EncodeDecodeError::InvalidExtensionLength(kind, length as usize))?;
+            }
+            res => res?
+        }
+
+        // Blocks unknown to the handler come from newer servers and are skipped
+        let remaining = block.limit();
+        if io::copy(&mut block, &mut io::sink())? != remaining {
+            return Err(EncodeDecodeError::InvalidExtensionLength(kind, length as usize))?;
+        }
+    }
+}
+
+// Shortens a string to at most max_length bytes, without splitting a character
+pub fn truncate_string(string: &mut String, max_length: usize) {
+    if string.len() <= max_length {
+        return;
+    }
+
+    let mut length = max_length;
+    while !string.is_char_boundary(length) {
+        length -= 1;
+    }
+
+    string.truncate(length);
+}
+
+// Fails if a string is longer than the decoder of the other side accepts
+pub fn check_length(field: &'static str, string: &str, max_length: usize) -> EncodeDecodeResult<()> {
+    if string.len() > max_length {
+        Err(EncodeDecodeError::FieldTooLong(field, string.len(), max_length))
+    } else {
+        Ok(())
+    }
+}
+
+// Per-entry strings, where an empty string stands for None
+pub fn string_list_len<'a>(list: impl Iterator<Item = Option<&'a str>>) -> usize {
+    list.map(|item| 4 + item.map_or(0, str::len)).sum()
+}
+
+pub fn write_string_list<'a, W: Write>(writer: &mut PayloadWriter<W>, field: &'static str, max_length: usize, list: impl Iterator<Item = Option<&'a str>>) -> EncodeDecodeResult<()> {
+    for item in list {
+        let item = item.unwrap_or_default();
+        check_length(field, item, max_length)?;
+
+        let item = item.as_bytes();
+
+        writer.write(&(item.len() as u32).to_be_bytes())?;
+        writer.write(item)?;
+    }
+
+    Ok(())
+}
+
+pub fn read_string_list(cursor: &mut impl Read, count: u16, max_length: u32) -> WhereResult<Vec<Option<String>>> {
+    (0..count)
+        .map(|_| {
+            let string = read_string_field(cursor, max_length)?;
+            Ok(Some(string).filter(|s| !s.is_empty()))
+        })
+        .collect()
+}
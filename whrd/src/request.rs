@@ -0,0 +1,82 @@
+use std::io::Cursor;
+
+use crate::error::{EncodeDecodeError, EncodeDecodeResult, WhereResult};
+use crate::{parse, MAX_USER_TTY_LENGTH, WHERED_MAGIC};
+
+// The magic, request kind, time range and an optional user name
+pub const MAX_REQUEST_LENGTH: usize = WHERED_MAGIC.len() + 1 + 8 * 2 + 1 + 4 + MAX_USER_TTY_LENGTH;
+
+const REQUEST_HISTORY: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Request {
+    Sessions,
+    History(HistoryQuery)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HistoryQuery {
+    pub since: i64,
+    pub until: i64,
+    pub user: Option<String>
+}
+
+impl Request {
+    pub fn to_udp_payload(&self) -> EncodeDecodeResult<Vec<u8>> {
+        let mut bytes: Vec<u8> = vec![];
+        bytes.extend(&WHERED_MAGIC);
+
+        // A bare magic is a session request, which is all older servers understand
+        if let Self::History(query) = self {
+            bytes.push(REQUEST_HISTORY);
+            bytes.extend(&query.since.to_be_bytes());
+            bytes.extend(&query.until.to_be_bytes());
+
+            match &query.user {
+                None => bytes.push(0u8),
+                Some(user) => {
+                    parse::check_length("user", user, MAX_USER_TTY_LENGTH)?;
+
+                    bytes.push(1u8);
+                    bytes.extend(&(user.len() as u32).to_be_bytes());
+                    bytes.extend(user.as_bytes());
+                }
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    pub fn from_udp_payload(buffer: &[u8]) -> WhereResult<Self> {
+        let mut cursor = Cursor::new(buffer);
+
+        parse::read_magic(&mut cursor, WHERED_MAGIC)?;
+
+        if cursor.position() as usize == buffer.len() {
+            return Ok(Self::Sessions);
+        }
+
+        let kind = parse::read_field(&mut cursor, |buf: [u8; 1]| Ok(buf[0]))?;
+
+        match kind {
+            REQUEST_HISTORY => {
+                let since = parse::read_field(&mut cursor, |buf| Ok(i64::from_be_bytes(buf)))?;
+                let until = parse::read_field(&mut cursor, |buf| Ok(i64::from_be_bytes(buf)))?;
+
+                let user = if parse::read_bool_field(&mut cursor)? {
+                    Some(parse::read_string_field(&mut cursor, MAX_USER_TTY_LENGTH as u32)?)
+                } else {
+                    None
+                };
+
+                Ok(Self::History(HistoryQuery {
+                    since,
+                    until,
+                    user
+                }))
+            }
+            _ => Err(EncodeDecodeError::UnknownRequestKind(kind))?
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use coreutils_core::libc;
+use coreutils_core::os::utmpx::*;
+
+use crate::error::WhereResult;
+use crate::Session;
+
+#[cfg(target_vendor = "apple")]
+const SYSTEM_UTMPX_PATH: &CStr = c"/var/run/utmpx";
+#[cfg(not(target_vendor = "apple"))]
+const SYSTEM_UTMPX_PATH: &CStr = c"/var/run/utmp";
+
+// The utmpx functions share a global cursor and file name, so only one
+// reader may use them at a time.
+static UTMPX_LOCK: Mutex<()> = Mutex::new(());
+
+pub trait SessionSource {
+    fn sessions(&self) -> WhereResult<Vec<Session>>;
+
+    // Files whose changes mean the list of sessions may have changed
+    fn watch_paths(&self) -> Vec<PathBuf> {
+        vec![]
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct UtmpxSource;
+
+impl SessionSource for UtmpxSource {
+    fn sessions(&self) -> WhereResult<Vec<Session>> {
+        let _guard = UTMPX_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(from_utmpx_set(UtmpxSet::system()))
+    }
+
+    fn watch_paths(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from(SYSTEM_UTMPX_PATH.to_string_lossy().as_ref())]
+    }
+}
+
+#[derive(Debug)]
+pub struct UtmpFileSource {
+    path: PathBuf,
+    dev_dir: PathBuf
+}
+
+impl UtmpFileSource {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            dev_dir: PathBuf::from("/dev")
+        }
+    }
+
+    // Where TTYs are looked up to tell whether sessions are still active,
+    // such as the /dev directory of the container the file belongs to
+    pub fn with_dev_dir(mut self, dev_dir: impl AsRef<Path>) -> Self {
+        self.dev_dir = dev_dir.as_ref().to_path_buf();
+        self
+    }
+}
+
+impl SessionSource for UtmpFileSource {
+    fn sessions(&self) -> WhereResult<Vec<Session>> {
+        let sessions = read_utmpx_file(&self.path)?
+            .into_iter()
+            .filter(|utmpx| utmpx.entry_type() == UtmpxKind::UserProcess || utmpx.entry_type() == UtmpxKind::DeadProcess)
+            .map(|utmpx| Session::from_utmpx(&utmpx, &self.dev_dir))
+            .collect();
+
+        Ok(sessions)
+    }
+
+    fn watch_paths(&self) -> Vec<PathBuf> {
+        vec![self.path.clone()]
+    }
+}
+
+// Records are returned in file order, with duplicates kept, which matters for
+// wtmp where the same login and logout may be recorded more than once
+pub(crate) fn read_utmpx_file(path: &Path) -> io::Result<Vec<Utmpx>> {
+    // The utmpx functions read nothing from files that are missing or cannot
+    // be read, instead of failing, so that would look like nobody logged in
+    File::open(path)?;
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut records = vec![];
+
+    let _guard = UTMPX_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    unsafe {
+        libc::utmpxname(path.as_ptr());
+        libc::setutxent();
+        loop {
+            let record = libc::getutxent();
+            if record.is_null() {
+                break;
+            }
+            records.push(Utmpx::from(*record));
+        }
+        libc::endutxent();
+
+        // Point the utmpx functions back at the system database, otherwise
+        // later reads through UtmpxSet::system() would keep using this file
+        libc::utmpxname(SYSTEM_UTMPX_PATH.as_ptr());
+    }
+
+    Ok(records)
+}
+
+pub(crate) fn from_utmpx_set(set: UtmpxSet) -> Vec<Session> {
+    set.into_iter()
+        .filter(|utmpx| utmpx.entry_type() == UtmpxKind::UserProcess || utmpx.entry_type() == UtmpxKind::DeadProcess)
+        .map(Session::from)
+        .collect()
+}
@@ -0,0 +1,163 @@
+use std::net::UdpSocket;
+use std::thread;
+use std::time::Duration;
+use whrd::client::{Client, Server};
+use whrd::error::WhereError;
+use whrd::{Request, Session, SessionCollection, MAX_REQUEST_LENGTH};
+
+// A server answering a single sessions request, after ignoring the first ones
+fn spawn_server(ignored: usize) -> (String, thread::JoinHandle<()>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let endpoint = socket.local_addr().unwrap().to_string();
+
+    let handle = thread::spawn(move || {
+        let mut buf = [0; MAX_REQUEST_LENGTH];
+
+        for _ in 0..ignored {
+            socket.recv_from(&mut buf).unwrap();
+        }
+
+        let (length, src) = socket.recv_from(&mut buf).unwrap();
+        assert_eq!(Request::from_udp_payload(&buf[..length]).unwrap(), Request::Sessions);
+
+        let collection = SessionCollection::from_vec(vec![Session::new("alice", "pts/0", 1_700_000_000)]);
+        socket.send_to(&collection.to_udp_payload().unwrap(), src).unwrap();
+    });
+
+    (endpoint, handle)
+}
+
+#[test]
+fn responses_per_server() {
+    let (endpoint, server) = spawn_server(1);
+
+    // Nothing listens on the port of a socket that was just closed
+    let unreachable = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+
+    let client = Client::new()
+        .with_timeout(Duration::from_millis(200))
+        .with_max_retries(2)
+        .with_server(Server::new(&endpoint).with_label("web"))
+        .with_server(Server::new(&unreachable).with_max_retries(1));
+
+    let responses = client.sessions();
+    server.join().unwrap();
+
+    assert_eq!(responses.len(), 2);
+
+    let response = &responses[0];
+    assert!(response.is_ok());
+    assert_eq!((response.label.as_str(), response.endpoint.as_str(), response.attempts), ("web", endpoint.as_str(), 2));
+    assert!(response.rtt.is_some_and(|rtt| rtt <= response.elapsed));
+
+    let sessions = response.result.as_ref().unwrap();
+    assert_eq!(sessions.iter().map(|s| s.host.as_deref()).collect::<Vec<_>>(), [Some("web")]);
+
+    let response = &responses[1];
+    assert_eq!(response.label, unreachable);
+    assert_eq!(response.attempts, 1);
+    assert!(response.rtt.is_none());
+    assert!(matches!(response.result, Err(WhereError::TimedOut(..)) | Err(WhereError::IOError(_))));
+
+    // Responses can be printed as they are, such as when debugging
+    assert!(format!("{response:?}").contains(&unreachable));
+}
+
+#[test]
+fn replies_from_other_addresses_are_dropped() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let endpoint = socket.local_addr().unwrap().to_string();
+
+    let server = thread::spawn(move || {
+        let mut buf = [0; MAX_REQUEST_LENGTH];
+        let (_, src) = socket.recv_from(&mut buf).unwrap();
+
+        // Someone else answering first, such as a spoofed reply
+        let collection = SessionCollection::from_vec(vec![Session::new("mallory", "pts/9", 1_700_000_000)]);
+        UdpSocket::bind("127.0.0.1:0").unwrap().send_to(&collection.to_udp_payload().unwrap(), src).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        let collection = SessionCollection::from_vec(vec![Session::new("alice", "pts/0", 1_700_000_000)]);
+        socket.send_to(&collection.to_udp_payload().unwrap(), src).unwrap();
+    });
+
+    let client = Client::new()
+        .with_timeout(Duration::from_secs(2))
+        .with_max_retries(1)
+        .with_server(Server::new(&endpoint));
+
+    let responses = client.sessions();
+    server.join().unwrap();
+
+    let sessions = responses[0].result.as_ref().unwrap();
+    assert_eq!(sessions.iter().map(|s| s.user.as_str()).collect::<Vec<_>>(), ["alice"]);
+}
+
+#[test]
+fn undecodable_replies_are_retried() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let endpoint = socket.local_addr().unwrap().to_string();
+
+    let server = thread::spawn(move || {
+        let mut buf = [0; MAX_REQUEST_LENGTH];
+
+        let (_, src) = socket.recv_from(&mut buf).unwrap();
+        socket.send_to(b"garbage", src).unwrap();
+
+        let (_, src) = socket.recv_from(&mut buf).unwrap();
+        let collection = SessionCollection::from_vec(vec![Session::new("alice", "pts/0", 1_700_000_000)]);
+        socket.send_to(&collection.to_udp_payload().unwrap(), src).unwrap();
+
+        // Only garbage for the next client
+        let (_, src) = socket.recv_from(&mut buf).unwrap();
+        socket.send_to(b"garbage", src).unwrap();
+        let (_, src) = socket.recv_from(&mut buf).unwrap();
+        socket.send_to(b"garbage", src).unwrap();
+    });
+
+    let client = Client::new()
+        .with_timeout(Duration::from_secs(2))
+        .with_max_retries(2)
+        .with_server(Server::new(&endpoint));
+
+    let response = client.sessions().remove(0);
+    assert_eq!(response.attempts, 2);
+    assert_eq!(response.result.unwrap().len(), 1);
+
+    // The last error is kept once there are no attempts left
+    let response = client.sessions().remove(0);
+    server.join().unwrap();
+    assert_eq!(response.attempts, 2);
+    assert!(matches!(response.result, Err(WhereError::EncodeDecodeError(_))));
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn async_replies_from_other_addresses_are_dropped() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let endpoint = socket.local_addr().unwrap().to_string();
+
+    let server = thread::spawn(move || {
+        let mut buf = [0; MAX_REQUEST_LENGTH];
+        let (_, src) = socket.recv_from(&mut buf).unwrap();
+
+        UdpSocket::bind("127.0.0.1:0").unwrap().send_to(b"garbage", src).unwrap();
+        socket.send_to(b"garbage", src).unwrap();
+
+        let (_, src) = socket.recv_from(&mut buf).unwrap();
+        let collection = SessionCollection::from_vec(vec![Session::new("alice", "pts/0", 1_700_000_000)]);
+        socket.send_to(&collection.to_udp_payload().unwrap(), src).unwrap();
+    });
+
+    let client = Client::new()
+        .with_timeout(Duration::from_secs(2))
+        .with_max_retries(2)
+        .with_server(Server::new(&endpoint));
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let response = runtime.block_on(client.sessions_async()).remove(0);
+    server.join().unwrap();
+
+    assert_eq!(response.attempts, 2);
+    assert_eq!(response.result.unwrap().len(), 1);
+}
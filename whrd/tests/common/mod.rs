@@ -0,0 +1,12 @@
+use whrd::HostInfo;
+
+pub fn host_info() -> HostInfo {
+    HostInfo {
+        hostname: "server.example.org".to_string(),
+        os: "Linux 6.1.0".to_string(),
+        uptime: 3600,
+        load_average: [12, 34, 56],
+        cpus: 8,
+        boot_time: 1_700_000_000
+    }
+}
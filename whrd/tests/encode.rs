@@ -0,0 +1,103 @@
+mod common;
+
+use common::host_info;
+use whrd::error::EncodeDecodeError;
+use whrd::{HistoryEntry, LoginHistory, Session, SessionCollection, MAX_PAYLOAD_LENGTH, MAX_REMOTE_LENGTH, MAX_USER_TTY_LENGTH};
+
+// A session as long as an entry can be
+fn long_session(index: usize) -> Session {
+    let user = format!("{index:0>width$}", width = MAX_USER_TTY_LENGTH);
+
+    Session::new(user, "t".repeat(MAX_USER_TTY_LENGTH), index as i64)
+        .with_remote("r".repeat(MAX_REMOTE_LENGTH))
+}
+
+#[test]
+fn encoded_lengths_match() {
+    let session = Session::new("alice", "pts/0", 1_700_000_000).with_remote("10.0.0.1");
+    assert_eq!(session.to_udp_payload().unwrap().len(), session.encoded_len());
+
+    let entry = HistoryEntry { session, logout_time: Some(1_700_000_100) };
+    assert_eq!(entry.to_udp_payload().unwrap().len(), entry.encoded_len());
+
+    let host_info = host_info();
+    assert_eq!(host_info.to_udp_payload().unwrap().len(), host_info.encoded_len());
+}
+
+#[test]
+fn write_matches_to_udp_payload() {
+    let collection = SessionCollection::from_parts(vec![long_session(0), long_session(1).with_container("web")], Some(host_info()));
+
+    let mut buffer = vec![];
+    let length = collection.write_udp_payload(&mut buffer).unwrap();
+    assert_eq!(length, buffer.len());
+    assert_eq!(buffer, collection.to_udp_payload().unwrap());
+
+    // Buffers are meant to be reused between payloads
+    buffer.clear();
+    collection.write_udp_payload(&mut buffer).unwrap();
+    assert_eq!(buffer, collection.to_udp_payload().unwrap());
+}
+
+#[test]
+fn full_payload() {
+    let collection: SessionCollection = (0..500).map(long_session).collect();
+
+    let mut buffer = vec![];
+    match collection.write_udp_payload(&mut buffer) {
+        Err(EncodeDecodeError::InvalidPayloadLength(length)) => assert!(length > MAX_PAYLOAD_LENGTH),
+        res => panic!("unexpected result: {res:?}")
+    }
+
+    // Nothing is written past the limit
+    assert!(buffer.len() <= MAX_PAYLOAD_LENGTH);
+}
+
+#[test]
+fn extension_that_does_not_fit() {
+    // Entries that leave just enough room for the end of the payload
+    let entry_length = long_session(0).encoded_len();
+    let count = (MAX_PAYLOAD_LENGTH - 4 - 2 - 1) / entry_length;
+
+    let mut sessions: Vec<_> = (0..count).map(long_session).collect();
+    assert!(SessionCollection::from_vec(sessions.clone()).to_udp_payload().is_ok());
+
+    sessions[0].container = Some("web".to_string());
+    let collection = SessionCollection::from_vec(sessions);
+
+    assert!(matches!(collection.to_udp_payload(), Err(EncodeDecodeError::InvalidPayloadLength(_))));
+}
+
+#[test]
+fn history_keeps_the_newest_entries() {
+    // Out of order, as wtmp files can be
+    let history: LoginHistory = (0..1000)
+        .rev()
+        .map(|index| HistoryEntry { session: long_session(index), logout_time: Some(index as i64 + 10) })
+        .collect();
+
+    let payload = history.to_udp_payload().unwrap();
+    assert!(payload.len() <= MAX_PAYLOAD_LENGTH);
+
+    let decoded = LoginHistory::from_udp_payload(&payload, "server").unwrap();
+    let login_times: Vec<_> = decoded.iter().map(|entry| entry.session.login_time).collect();
+
+    assert!(login_times.len() < 1000);
+    assert_eq!(login_times.last(), Some(&999));
+
+    // Only the oldest entries are left out, and the rest is sorted
+    let first = 1000 - login_times.len() as i64;
+    assert!(login_times.iter().copied().eq(first..1000));
+}
+
+#[test]
+fn history_that_fits_is_kept_whole() {
+    let history: LoginHistory = (0..10)
+        .map(|index| HistoryEntry { session: long_session(index), logout_time: None })
+        .collect();
+
+    let payload = history.to_udp_payload().unwrap();
+    let decoded = LoginHistory::from_udp_payload(&payload, "server").unwrap();
+
+    assert_eq!(decoded.len(), 10);
+}
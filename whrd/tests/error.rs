@@ -0,0 +1,96 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use whrd::error::{EncodeDecodeError, ErrorCode, Report, WhereError};
+
+fn report(error: &dyn Error) -> String {
+    Report(error).to_string()
+}
+
+#[derive(Debug)]
+struct Cause;
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("root cause")
+    }
+}
+
+impl Error for Cause {}
+
+#[derive(Debug)]
+struct Wrapper(Cause);
+
+impl fmt::Display for Wrapper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("wrapper")
+    }
+}
+
+impl Error for Wrapper {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+#[test]
+fn wrapped_errors_are_reported_once() {
+    let error = WhereError::from(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+    assert_eq!(report(&error), "Input/output error: no such file");
+
+    let error = WhereError::from(EncodeDecodeError::from(io::Error::new(io::ErrorKind::UnexpectedEof, "early eof")));
+    assert_eq!(report(&error), "Encode/decode error: Input/output error while encoding/decoding: early eof");
+
+    let error = WhereError::from(String::from_utf8(vec![0xff]).unwrap_err());
+    assert_eq!(report(&error).matches("invalid utf-8").count(), 1);
+}
+
+#[test]
+fn deeper_sources_are_kept() {
+    let error = WhereError::from(io::Error::other(Wrapper(Cause)));
+
+    assert_eq!(report(&error), "Input/output error: wrapper: root cause");
+    assert_eq!(error.source().unwrap().to_string(), "wrapper");
+}
+
+#[test]
+fn boxed_errors() {
+    fn decode() -> Result<(), Box<dyn Error>> {
+        Err(WhereError::from(EncodeDecodeError::NonbinaryBoolean))?
+    }
+
+    let error = decode().unwrap_err();
+
+    assert!(error.downcast_ref::<WhereError>().is_some());
+    assert_eq!(error.to_string(), "Encode/decode error");
+    assert_eq!(report(error.as_ref()), "Encode/decode error: Boolean value is not 0 or 1");
+    assert!(error.source().unwrap().source().is_none());
+}
+
+#[test]
+fn wrapped_errors_are_sources() {
+    let error = WhereError::from(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+
+    assert_eq!(error.to_string(), "Input/output error");
+    assert!(error.source().unwrap().downcast_ref::<io::Error>().is_some());
+}
+
+#[test]
+fn access_denied() {
+    let error = WhereError::from(io::Error::from(io::ErrorKind::PermissionDenied));
+    assert!(matches!(error, WhereError::AccessDenied(_)));
+    assert_eq!(error.code(), ErrorCode::AccessDenied);
+
+    let error = WhereError::from(io::Error::from(io::ErrorKind::ConnectionRefused));
+    assert!(matches!(error, WhereError::IOError(_)));
+    assert_eq!(error.code(), ErrorCode::Io);
+}
+
+#[test]
+fn codes() {
+    assert_eq!(WhereError::from(EncodeDecodeError::VersionMismatch(*b"WHRH", *b"WHRD")).code(), ErrorCode::ProtocolVersion);
+    assert_eq!(WhereError::from(EncodeDecodeError::UnknownRequestKind(9)).code(), ErrorCode::ProtocolVersion);
+    assert_eq!(WhereError::from(EncodeDecodeError::IncorrectEntryCount).code(), ErrorCode::Protocol);
+    assert_eq!(WhereError::from("nope".parse::<std::net::IpAddr>().unwrap_err()).code(), ErrorCode::Address);
+    assert_eq!(ErrorCode::ProtocolVersion.to_string(), "protocol_version");
+}
@@ -0,0 +1,67 @@
+use whrd::{HistoryEntry, HistoryQuery, Session};
+
+fn entry(user: &str, login_time: i64, logout_time: Option<i64>) -> HistoryEntry {
+    HistoryEntry {
+        session: Session::new(user, "pts/0", login_time),
+        logout_time
+    }
+}
+
+fn query(since: i64, until: i64) -> HistoryQuery {
+    HistoryQuery {
+        since,
+        until,
+        user: None
+    }
+}
+
+#[test]
+fn sessions_overlapping_the_range_match() {
+    let query = query(1000, 2000);
+
+    // Started before the range and ended in it, or still open
+    assert!(query.matches(&entry("alice", 500, Some(1500))));
+    assert!(query.matches(&entry("alice", 500, None)));
+    // Spanning the whole range
+    assert!(query.matches(&entry("alice", 500, Some(2500))));
+    // Entirely within the range, or starting in it
+    assert!(query.matches(&entry("alice", 1200, Some(1300))));
+    assert!(query.matches(&entry("alice", 1800, Some(2500))));
+    // Bounds are inclusive
+    assert!(query.matches(&entry("alice", 500, Some(1000))));
+    assert!(query.matches(&entry("alice", 2000, None)));
+}
+
+#[test]
+fn sessions_outside_the_range_do_not_match() {
+    let query = query(1000, 2000);
+
+    assert!(!query.matches(&entry("alice", 500, Some(999))));
+    assert!(!query.matches(&entry("alice", 2001, Some(2500))));
+    assert!(!query.matches(&entry("alice", 2001, None)));
+}
+
+#[test]
+fn user_filter() {
+    let query = HistoryQuery {
+        user: Some("alice".to_string()),
+        ..query(0, i64::MAX)
+    };
+
+    assert!(query.matches(&entry("alice", 500, None)));
+    assert!(!query.matches(&entry("bob", 500, None)));
+}
+
+#[test]
+fn durations() {
+    assert_eq!(entry("alice", 1000, Some(1600)).duration(5000), 600);
+    assert_eq!(entry("alice", 1000, None).duration(5000), 4000);
+    // Clocks going backwards
+    assert_eq!(entry("alice", 1000, Some(900)).duration(5000), 0);
+}
+
+#[test]
+fn durations_do_not_overflow() {
+    assert_eq!(entry("alice", i64::MIN, Some(i64::MAX)).duration(0), i64::MAX);
+    assert_eq!(entry("alice", i64::MAX, None).duration(i64::MIN), 0);
+}
@@ -0,0 +1,12 @@
+use whrd::HostInfo;
+
+#[test]
+fn system_host_info() {
+    let info = HostInfo::system().unwrap();
+
+    assert!(!info.hostname.is_empty());
+    assert!(info.cpus > 0);
+    assert!(info.boot_time > 0);
+    assert!(info.load_average_f64().iter().all(|load| *load >= 0.0));
+    assert!(info.validate().is_ok());
+}
@@ -0,0 +1,252 @@
+mod common;
+
+use common::host_info;
+use whrd::error::{EncodeDecodeError, WhereError};
+use whrd::{HistoryEntry, LoginHistory, Session, SessionCollection, WHERED_HISTORY_MAGIC, WHERED_MAGIC};
+
+const HOST: &str = "server";
+
+fn sessions() -> SessionCollection {
+    let sessions = vec![
+        Session::new("alice", "pts/0", 1_700_000_100).with_pid(42).with_remote("10.0.0.1"),
+        Session::new("bob", "tty1", 1_700_000_200).with_active(false).with_container("web"),
+        Session::new("carol", "pts/1", 1_700_000_300).with_pid(7)
+    ];
+
+    SessionCollection::from_parts(sessions, Some(host_info()))
+}
+
+// An entry as encoded by servers, without any extension block
+fn raw_entry(user: &str) -> Vec<u8> {
+    let mut bytes = vec![];
+    bytes.extend(1i32.to_be_bytes());
+    bytes.extend(1_700_000_000i64.to_be_bytes());
+    bytes.extend((user.len() as u32).to_be_bytes());
+    bytes.extend(user.as_bytes());
+    bytes.extend(4u32.to_be_bytes());
+    bytes.extend(b"pts0");
+    bytes.extend([0, 1]);
+    bytes
+}
+
+fn raw_payload(count: u16, users: &[&str]) -> Vec<u8> {
+    let mut bytes = WHERED_MAGIC.to_vec();
+    bytes.extend(count.to_be_bytes());
+
+    for user in users {
+        bytes.extend(raw_entry(user));
+    }
+
+    bytes
+}
+
+fn decode_error(payload: &[u8]) -> EncodeDecodeError {
+    match SessionCollection::from_udp_payload(payload, HOST) {
+        Err(WhereError::EncodeDecodeError(e)) => e,
+        Err(e) => panic!("unexpected error: {e}"),
+        Ok(collection) => panic!("unexpectedly decoded {collection:?}")
+    }
+}
+
+// Decoded sessions have the host they were received from, unless they were relayed
+fn with_host(collection: SessionCollection) -> SessionCollection {
+    collection.into_iter()
+        .map(|mut session| {
+            session.host.get_or_insert_with(|| HOST.to_string());
+            session
+        })
+        .collect::<SessionCollection>()
+}
+
+#[test]
+fn sessions_round_trip() {
+    let collection = sessions();
+    let payload = collection.to_udp_payload().unwrap();
+    let decoded = SessionCollection::from_udp_payload(&payload, HOST).unwrap();
+
+    let mut expected = with_host(collection);
+    expected.set_host_info(Some(host_info()));
+    assert_eq!(decoded, expected);
+}
+
+#[test]
+fn relayed_sessions_keep_their_host() {
+    let mut session = Session::new("dave", "pts/2", 1_700_000_400);
+    session.host = Some("upstream".to_string());
+
+    let collection = SessionCollection::from_vec(vec![session, Session::new("erin", "pts/3", 1_700_000_500)]);
+    let payload = collection.to_udp_payload().unwrap();
+    let decoded = SessionCollection::from_udp_payload(&payload, HOST).unwrap();
+
+    let hosts: Vec<_> = decoded.iter().map(|s| s.host.as_deref()).collect();
+    assert_eq!(hosts, [Some("upstream"), Some(HOST)]);
+}
+
+#[test]
+fn empty_collection_round_trip() {
+    let payload = SessionCollection::get_empty().to_udp_payload().unwrap();
+    let decoded = SessionCollection::from_udp_payload(&payload, HOST).unwrap();
+
+    assert!(decoded.is_empty());
+    assert_eq!(decoded.host_info(), None);
+}
+
+#[test]
+fn history_round_trip() {
+    let history: LoginHistory = [
+        HistoryEntry { session: Session::new("alice", "pts/0", 1_700_000_100), logout_time: Some(1_700_000_900) },
+        HistoryEntry { session: Session::new("bob", "tty1", 1_700_000_200).with_remote("10.0.0.2"), logout_time: None }
+    ].into_iter().collect();
+
+    let payload = history.to_udp_payload().unwrap();
+    let decoded = LoginHistory::from_udp_payload(&payload, HOST).unwrap();
+
+    let expected: Vec<_> = history.into_iter()
+        .map(|mut entry| {
+            entry.session.host = Some(HOST.to_string());
+            entry
+        })
+        .collect();
+    assert_eq!(decoded.into_vec(), expected);
+}
+
+#[test]
+fn from_reader_leaves_the_rest_unread() {
+    let mut payload = sessions().to_udp_payload().unwrap();
+    payload.extend(b"rest");
+
+    let mut reader = payload.as_slice();
+    let decoded = SessionCollection::from_reader(&mut reader, HOST).unwrap();
+
+    assert_eq!(decoded.len(), 3);
+    assert_eq!(reader, b"rest");
+}
+
+#[test]
+fn baseline_payload_without_end_marker() {
+    let payload = raw_payload(2, &["alice", "bob"]);
+    let decoded = SessionCollection::from_udp_payload(&payload, HOST).unwrap();
+
+    let users: Vec<_> = decoded.iter().map(|s| s.user.as_str()).collect();
+    assert_eq!(users, ["alice", "bob"]);
+    assert_eq!(decoded.host_info(), None);
+}
+
+#[test]
+fn truncated_entry() {
+    let mut payload = raw_payload(2, &["alice", "bob"]);
+    payload.truncate(payload.len() - 3);
+
+    assert!(matches!(decode_error(&payload), EncodeDecodeError::IncorrectEntryCount));
+}
+
+#[test]
+fn missing_entry() {
+    let payload = raw_payload(3, &["alice", "bob"]);
+
+    assert!(matches!(decode_error(&payload), EncodeDecodeError::IncorrectEntryCount));
+}
+
+#[test]
+fn trailing_data() {
+    let mut payload = sessions().to_udp_payload().unwrap();
+    payload.extend(b"garbage");
+
+    assert!(matches!(decode_error(&payload), EncodeDecodeError::TrailingData(7)));
+}
+
+#[test]
+fn nonbinary_boolean() {
+    let mut payload = raw_payload(1, &["alice"]);
+    // The active flag of the last entry
+    *payload.last_mut().unwrap() = 2;
+
+    assert!(matches!(decode_error(&payload), EncodeDecodeError::NonbinaryBoolean));
+}
+
+#[test]
+fn empty_remote() {
+    let mut payload = raw_payload(1, &["alice"]);
+    payload.truncate(payload.len() - 2);
+    payload.push(1);
+    payload.extend(0u32.to_be_bytes());
+    payload.push(1);
+
+    assert!(matches!(decode_error(&payload), EncodeDecodeError::EmptyRemote));
+}
+
+#[test]
+fn empty_remote_is_sent_as_local() {
+    let collection = SessionCollection::from_parts(vec![Session::new("alice", "pts/0", 1_700_000_100).with_remote("")], None);
+    let payload = collection.to_udp_payload().unwrap();
+    let decoded = SessionCollection::from_udp_payload(&payload, HOST).unwrap();
+
+    assert_eq!(decoded.iter().next().unwrap().remote, None);
+}
+
+#[test]
+fn truncated_extension() {
+    let mut payload = raw_payload(1, &["alice"]);
+    payload.push(9);
+    payload.extend(10u16.to_be_bytes());
+    payload.extend(b"abc");
+
+    assert!(matches!(decode_error(&payload), EncodeDecodeError::InvalidExtensionLength(9, 10)));
+}
+
+#[test]
+fn truncated_host_info() {
+    let collection = SessionCollection::from_parts(vec![Session::new("alice", "pts/0", 1_700_000_100)], Some(host_info()));
+    let mut payload = collection.to_udp_payload().unwrap();
+
+    // Cut the payload in the middle of the host information, which is the last block
+    payload.truncate(payload.len() - 20);
+
+    assert!(matches!(decode_error(&payload), EncodeDecodeError::InvalidExtensionLength(1, _)));
+}
+
+#[test]
+fn overflowing_extension() {
+    // A list of one container declared as shorter than it is
+    let mut payload = raw_payload(1, &["alice"]);
+    payload.push(2);
+    payload.extend(4u16.to_be_bytes());
+    payload.extend(3u32.to_be_bytes());
+    payload.extend(b"web");
+    payload.push(0);
+
+    assert!(matches!(decode_error(&payload), EncodeDecodeError::InvalidExtensionLength(2, 4)));
+}
+
+#[test]
+fn unknown_extensions_are_skipped() {
+    let mut payload = raw_payload(1, &["alice"]);
+    payload.push(9);
+    payload.extend(3u16.to_be_bytes());
+    payload.extend(b"abc");
+    payload.push(0);
+
+    let decoded = SessionCollection::from_udp_payload(&payload, HOST).unwrap();
+    assert_eq!(decoded.len(), 1);
+}
+
+#[test]
+fn bad_magic() {
+    let mut payload = raw_payload(1, &["alice"]);
+    payload[..4].copy_from_slice(b"NOPE");
+
+    assert!(matches!(decode_error(&payload), EncodeDecodeError::BadMagic(magic) if magic == *b"NOPE"));
+}
+
+#[test]
+fn version_mismatch() {
+    let payload = raw_payload(0, &[]);
+
+    match LoginHistory::from_udp_payload(&payload, HOST) {
+        Err(WhereError::EncodeDecodeError(EncodeDecodeError::VersionMismatch(expected, found))) => {
+            assert_eq!(expected, WHERED_HISTORY_MAGIC);
+            assert_eq!(found, WHERED_MAGIC);
+        }
+        res => panic!("unexpected result: {:?}", res.map(LoginHistory::into_vec))
+    }
+}
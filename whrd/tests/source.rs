@@ -0,0 +1,85 @@
+use std::io;
+use whrd::error::{WhereError, WhereResult};
+use whrd::{HostInfo, Session, SessionCollection, SessionSource};
+
+struct FakeSource {
+    sessions: Vec<Session>
+}
+
+impl SessionSource for FakeSource {
+    fn sessions(&self) -> WhereResult<Vec<Session>> {
+        Ok(self.sessions.clone())
+    }
+}
+
+struct FailingSource;
+
+impl SessionSource for FailingSource {
+    fn sessions(&self) -> WhereResult<Vec<Session>> {
+        Err(io::Error::new(io::ErrorKind::NotFound, "no sessions here").into())
+    }
+}
+
+fn fake_source() -> FakeSource {
+    FakeSource {
+        sessions: vec![
+            Session::new("alice", "pts/0", 1_700_000_100).with_remote("10.0.0.1"),
+            Session::new("bob", "tty1", 1_700_000_200).with_active(false),
+            Session::new("alice", "pts/1", 1_700_000_300).with_container("web")
+        ]
+    }
+}
+
+#[test]
+fn fetch_from_a_fake_source() {
+    let source = fake_source();
+    let collection = SessionCollection::fetch_from(&source, |_| true).unwrap();
+
+    // The sessions may not be the ones of this host
+    assert_eq!(collection.host_info(), None);
+    assert_eq!(collection.into_iter().collect::<Vec<_>>(), source.sessions);
+}
+
+#[test]
+fn fetch_from_applies_the_filter() {
+    let collection = SessionCollection::fetch_from(&fake_source(), |session| session.user == "alice").unwrap();
+    let ttys: Vec<_> = collection.iter().map(|s| s.tty.as_str()).collect();
+
+    assert_eq!(ttys, ["pts/0", "pts/1"]);
+
+    let collection = SessionCollection::fetch_from(&fake_source(), |session| session.active).unwrap();
+    assert!(collection.iter().all(|s| s.user == "alice"));
+
+    let collection = SessionCollection::fetch_from(&fake_source(), |_| false).unwrap();
+    assert!(collection.is_empty());
+}
+
+#[test]
+fn fetch_from_a_boxed_source() {
+    let source: Box<dyn SessionSource> = Box::new(fake_source());
+    let collection = SessionCollection::fetch_from(source.as_ref(), |session| session.container.is_some()).unwrap();
+
+    assert_eq!(collection.len(), 1);
+}
+
+#[test]
+fn source_errors_are_returned() {
+    match SessionCollection::fetch_from(&FailingSource, |_| true) {
+        Err(WhereError::IOError(e)) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+        res => panic!("unexpected result: {res:?}")
+    }
+
+    // Sources have no files to watch unless they say so
+    assert!(FailingSource.watch_paths().is_empty());
+}
+
+#[test]
+fn host_info_from_the_caller() {
+    let host_info = HostInfo::system().unwrap();
+    let collection = SessionCollection::fetch_from(&fake_source(), |_| true)
+        .unwrap()
+        .with_host_info(Some(host_info.clone()));
+
+    assert_eq!(collection.host_info(), Some(&host_info));
+    assert_eq!(collection.len(), 3);
+}
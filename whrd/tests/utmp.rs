@@ -0,0 +1,222 @@
+#![cfg(target_os = "linux")]
+
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use coreutils_core::libc;
+use coreutils_core::os::utmpx::{UtmpxKind, UtmpxSet};
+use whrd::error::{ErrorCode, WhereError};
+use whrd::{HistoryQuery, LoginHistory, Session, SessionCollection, SessionSource, UtmpFileSource};
+
+// The utmpx functions share a global file name, so tests reading files
+// directly must not run alongside each other
+static UTMPX_LOCK: Mutex<()> = Mutex::new(());
+
+fn copy_str(dest: &mut [libc::c_char], value: &str) {
+    for (dest, byte) in dest.iter_mut().zip(value.bytes()) {
+        *dest = byte as libc::c_char;
+    }
+}
+
+fn record(kind: libc::c_short, user: &str, tty: &str, host: &str, pid: i32, login_time: i64) -> libc::utmpx {
+    let mut record: libc::utmpx = unsafe { std::mem::zeroed() };
+    record.ut_type = kind;
+    record.ut_pid = pid;
+    copy_str(&mut record.ut_user, user);
+    copy_str(&mut record.ut_line, tty);
+    copy_str(&mut record.ut_host, host);
+    record.ut_tv.tv_sec = login_time as _;
+    record
+}
+
+struct Fixture {
+    dir: PathBuf
+}
+
+impl Fixture {
+    fn new(name: &str) -> Self {
+        Self::with_records(name, &[
+            record(libc::BOOT_TIME, "reboot", "~", "6.1.0", 0, 1_700_000_000),
+            record(libc::LOGIN_PROCESS, "LOGIN", "tty1", "", 10, 1_700_000_010),
+            // /dev/console exists, unlike /dev/pts/99
+            record(libc::USER_PROCESS, "alice", "console", "10.0.0.1", 42, 1_700_000_100),
+            record(libc::USER_PROCESS, "bob", "pts/99", "", 43, 1_700_000_200),
+            record(libc::DEAD_PROCESS, "carol", "tty", "", 44, 1_700_000_300)
+        ])
+    }
+
+    fn with_records(name: &str, records: &[libc::utmpx]) -> Self {
+        let dir = std::env::temp_dir().join(format!("whrd-utmp-test-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let bytes: Vec<u8> = records.iter()
+            .flat_map(|record| {
+                let bytes = unsafe {
+                    std::slice::from_raw_parts(record as *const libc::utmpx as *const u8, size_of::<libc::utmpx>())
+                };
+                bytes.to_vec()
+            })
+            .collect();
+        std::fs::write(dir.join("utmp"), bytes).unwrap();
+
+        Self { dir }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.join("utmp")
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+// The system source reads records into a set, so their order is lost
+fn sorted(mut sessions: Vec<Session>) -> Vec<Session> {
+    sessions.sort_by_key(|session| session.login_time);
+    sessions
+}
+
+fn expected() -> Vec<Session> {
+    vec![
+        Session::new("alice", "console", 1_700_000_100).with_pid(42).with_remote("10.0.0.1"),
+        Session::new("bob", "pts/99", 1_700_000_200).with_pid(43).with_active(false),
+        Session::new("carol", "tty", 1_700_000_300).with_pid(44).with_active(false)
+    ]
+}
+
+#[test]
+fn file_source_reads_user_sessions() {
+    let _guard = UTMPX_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let fixture = Fixture::new("file");
+
+    assert_eq!(UtmpFileSource::new(fixture.path()).sessions().unwrap(), expected());
+}
+
+#[test]
+fn file_and_system_sources_agree() {
+    let _guard = UTMPX_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let fixture = Fixture::new("system");
+
+    // The system source converts records the same way, only from another file
+    let set = UtmpxSet::from_file(fixture.path()).unwrap();
+    let system: Vec<Session> = set.into_iter()
+        .filter(|utmpx| matches!(utmpx.entry_type(), UtmpxKind::UserProcess | UtmpxKind::DeadProcess))
+        .map(Session::from)
+        .collect();
+
+    assert_eq!(sorted(UtmpFileSource::new(fixture.path()).sessions().unwrap()), sorted(system));
+}
+
+#[test]
+fn file_source_with_dev_dir() {
+    let _guard = UTMPX_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let fixture = Fixture::new("dev");
+
+    let dev_dir = fixture.dir.join("dev");
+    std::fs::create_dir_all(dev_dir.join("pts")).unwrap();
+    std::fs::write(dev_dir.join("pts/99"), b"").unwrap();
+
+    let sessions = UtmpFileSource::new(fixture.path()).with_dev_dir(&dev_dir).sessions().unwrap();
+    let active: Vec<_> = sessions.into_iter()
+        .map(|session| (session.user, session.active))
+        .collect();
+
+    assert_eq!(active, [("alice".to_string(), false), ("bob".to_string(), true), ("carol".to_string(), false)]);
+}
+
+#[test]
+fn utmp_file_has_no_host_info() {
+    let _guard = UTMPX_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let fixture = Fixture::new("collection");
+
+    let collection = SessionCollection::from_utmp_file(fixture.path()).unwrap();
+    assert_eq!(collection.host_info(), None);
+    assert_eq!(collection.into_iter().collect::<Vec<_>>(), expected());
+}
+
+#[test]
+fn missing_utmp_file() {
+    let error = UtmpFileSource::new("/nonexistent/utmp").sessions().unwrap_err();
+    assert!(matches!(error, WhereError::IOError(e) if e.kind() == io::ErrorKind::NotFound));
+}
+
+#[test]
+fn unreadable_utmp_file() {
+    let fixture = Fixture::new("unreadable");
+    std::fs::set_permissions(fixture.path(), std::fs::Permissions::from_mode(0o000)).unwrap();
+
+    // Permissions do not apply to root
+    if std::fs::File::open(fixture.path()).is_ok() {
+        return;
+    }
+
+    let error = SessionCollection::from_utmp_file(fixture.path()).unwrap_err();
+    assert!(matches!(error, WhereError::AccessDenied(_)));
+    assert_eq!(error.code(), ErrorCode::AccessDenied);
+}
+
+#[test]
+fn wtmp_records_are_paired_in_file_order() {
+    let _guard = UTMPX_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    // Logging out and back in within the same second, so only the order of
+    // the records tells which logout belongs to which login
+    let fixture = Fixture::with_records("wtmp", &[
+        record(libc::BOOT_TIME, "reboot", "~", "6.1.0", 0, 1_700_000_000),
+        record(libc::USER_PROCESS, "alice", "pts/0", "", 42, 1_700_000_100),
+        record(libc::DEAD_PROCESS, "", "pts/0", "", 42, 1_700_000_100),
+        record(libc::USER_PROCESS, "alice", "pts/0", "", 43, 1_700_000_100),
+        record(libc::DEAD_PROCESS, "", "pts/0", "", 43, 1_700_000_200),
+        record(libc::USER_PROCESS, "bob", "pts/1", "", 44, 1_700_000_300)
+    ]);
+
+    let query = HistoryQuery {
+        since: 0,
+        until: i64::MAX,
+        user: None
+    };
+    let history = LoginHistory::from_wtmp_file(fixture.path(), &query).unwrap();
+    let entries: Vec<_> = history.iter()
+        .map(|entry| (entry.session.user.as_str(), entry.session.pid, entry.logout_time))
+        .collect();
+
+    assert_eq!(entries, [
+        ("alice", 42, Some(1_700_000_100)),
+        ("alice", 43, Some(1_700_000_200)),
+        ("bob", 44, None)
+    ]);
+}
+
+#[test]
+fn missing_wtmp_file() {
+    let query = HistoryQuery {
+        since: 0,
+        until: i64::MAX,
+        user: None
+    };
+    let error = LoginHistory::from_wtmp_file("/nonexistent/wtmp", &query).unwrap_err();
+    assert!(matches!(error, WhereError::IOError(e) if e.kind() == io::ErrorKind::NotFound));
+}
+
+#[test]
+fn unreadable_wtmp_file() {
+    let fixture = Fixture::new("unreadable-wtmp");
+    std::fs::set_permissions(fixture.path(), std::fs::Permissions::from_mode(0o000)).unwrap();
+
+    // Permissions do not apply to root
+    if std::fs::File::open(fixture.path()).is_ok() {
+        return;
+    }
+
+    let query = HistoryQuery {
+        since: 0,
+        until: i64::MAX,
+        user: None
+    };
+    let error = LoginHistory::from_wtmp_file(fixture.path(), &query).unwrap_err();
+    assert_eq!(error.code(), ErrorCode::AccessDenied);
+}
@@ -0,0 +1,146 @@
+mod common;
+
+use common::host_info;
+use whrd::error::EncodeDecodeError;
+use whrd::{HistoryEntry, HistoryQuery, LoginHistory, Request, Session, SessionCollection, MAX_CONTAINER_LENGTH, MAX_OS_LENGTH, MAX_REMOTE_LENGTH, MAX_USER_TTY_LENGTH};
+
+fn session() -> Session {
+    Session::new("alice", "pts/0", 1_700_000_000)
+}
+
+fn assert_too_long<T: std::fmt::Debug>(result: Result<T, EncodeDecodeError>, field: &str, length: usize, max_length: usize) {
+    match result {
+        Err(EncodeDecodeError::FieldTooLong(name, actual, max)) => {
+            assert_eq!((name, actual, max), (field, length, max_length));
+        }
+        res => panic!("expected {field} to be too long, got {res:?}")
+    }
+}
+
+#[test]
+fn user_too_long() {
+    let mut session = session();
+    session.user = "u".repeat(200);
+
+    assert_too_long(session.validate(), "user", 200, MAX_USER_TTY_LENGTH);
+    assert_too_long(session.to_udp_payload(), "user", 200, MAX_USER_TTY_LENGTH);
+    assert_too_long(SessionCollection::from_vec(vec![session.clone()]).to_udp_payload(), "user", 200, MAX_USER_TTY_LENGTH);
+
+    let history = LoginHistory::from_vec(vec![HistoryEntry { session, logout_time: None }]);
+    assert_too_long(history.to_udp_payload(), "user", 200, MAX_USER_TTY_LENGTH);
+}
+
+#[test]
+fn tty_too_long() {
+    let mut session = session();
+    session.tty = "t".repeat(MAX_USER_TTY_LENGTH + 1);
+
+    assert_too_long(SessionCollection::from_vec(vec![session]).to_udp_payload(), "tty", MAX_USER_TTY_LENGTH + 1, MAX_USER_TTY_LENGTH);
+}
+
+#[test]
+fn remote_too_long() {
+    let session = session().with_remote("r".repeat(MAX_REMOTE_LENGTH + 1));
+
+    assert_too_long(SessionCollection::from_vec(vec![session]).to_udp_payload(), "remote", MAX_REMOTE_LENGTH + 1, MAX_REMOTE_LENGTH);
+}
+
+#[test]
+fn container_too_long() {
+    let session = session().with_container("c".repeat(MAX_CONTAINER_LENGTH + 1));
+
+    assert_too_long(SessionCollection::from_vec(vec![session]).to_udp_payload(), "container", MAX_CONTAINER_LENGTH + 1, MAX_CONTAINER_LENGTH);
+}
+
+#[test]
+fn host_too_long() {
+    let mut session = session();
+    session.host = Some("h".repeat(MAX_REMOTE_LENGTH + 1));
+
+    assert_too_long(SessionCollection::from_vec(vec![session]).to_udp_payload(), "host", MAX_REMOTE_LENGTH + 1, MAX_REMOTE_LENGTH);
+}
+
+#[test]
+fn hostname_too_long() {
+    let mut host_info = host_info();
+    host_info.hostname = "h".repeat(MAX_REMOTE_LENGTH + 1);
+
+    assert_too_long(host_info.validate(), "hostname", MAX_REMOTE_LENGTH + 1, MAX_REMOTE_LENGTH);
+    assert_too_long(SessionCollection::from_parts(vec![], Some(host_info)).to_udp_payload(), "hostname", MAX_REMOTE_LENGTH + 1, MAX_REMOTE_LENGTH);
+}
+
+#[test]
+fn os_too_long() {
+    let mut host_info = host_info();
+    host_info.os = "o".repeat(MAX_OS_LENGTH + 1);
+
+    assert_too_long(host_info.to_udp_payload(), "os", MAX_OS_LENGTH + 1, MAX_OS_LENGTH);
+}
+
+#[test]
+fn history_query_user_too_long() {
+    let request = Request::History(HistoryQuery {
+        since: 0,
+        until: i64::MAX,
+        user: Some("u".repeat(MAX_USER_TTY_LENGTH + 1))
+    });
+
+    assert_too_long(request.to_udp_payload(), "user", MAX_USER_TTY_LENGTH + 1, MAX_USER_TTY_LENGTH);
+}
+
+#[test]
+fn fields_at_their_limit_are_accepted() {
+    let mut session = session()
+        .with_remote("r".repeat(MAX_REMOTE_LENGTH))
+        .with_container("c".repeat(MAX_CONTAINER_LENGTH));
+    session.user = "u".repeat(MAX_USER_TTY_LENGTH);
+    session.tty = "t".repeat(MAX_USER_TTY_LENGTH);
+    session.host = Some("h".repeat(MAX_REMOTE_LENGTH));
+
+    assert!(SessionCollection::from_vec(vec![session]).to_udp_payload().is_ok());
+}
+
+#[test]
+fn truncate_ascii() {
+    let mut session = session().with_remote("r".repeat(100));
+    session.user = "u".repeat(100);
+    session.truncate_to_limits();
+
+    assert_eq!(session.user, "u".repeat(MAX_USER_TTY_LENGTH));
+    assert_eq!(session.remote, Some("r".repeat(MAX_REMOTE_LENGTH)));
+    assert!(session.validate().is_ok());
+}
+
+#[test]
+fn truncate_on_character_boundaries() {
+    // 3 bytes each, so the limit falls in the middle of the 11th one
+    let mut session = session();
+    session.user = "€".repeat(20);
+    session.truncate_to_limits();
+
+    assert_eq!(session.user, "€".repeat(MAX_USER_TTY_LENGTH / 3));
+
+    // 4 bytes each, with the limit right after the 8th one
+    session.tty = "🦀".repeat(20);
+    session.truncate_to_limits();
+
+    assert_eq!(session.tty, "🦀".repeat(MAX_USER_TTY_LENGTH / 4));
+
+    // A multibyte character right across the limit
+    let mut remote = "r".repeat(MAX_REMOTE_LENGTH - 1);
+    remote.push('é');
+    let mut session = session.with_remote(remote);
+    session.truncate_to_limits();
+
+    assert_eq!(session.remote, Some("r".repeat(MAX_REMOTE_LENGTH - 1)));
+    assert!(session.validate().is_ok());
+}
+
+#[test]
+fn truncate_leaves_short_fields() {
+    let mut session = session().with_container("web");
+    let expected = session.clone();
+    session.truncate_to_limits();
+
+    assert_eq!(session, expected);
+}